@@ -0,0 +1,72 @@
+// Compiles the C constants of the MPI interface that `mpi-sys` does not provide.
+// Detects the version of the MPI standard that the MPI library implements.
+//
+// The `mpi4` cfg is set for MPI 4.0 and later, which enables the MPI-4 persistent
+// neighbourhood collectives. The version is read from the `MPI_VERSION` macro of `mpi.h` with
+// the C preprocessor. The MPI library is found in the same way as in `mpi-sys`.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rustc-check-cfg=cfg(mpi4)");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/mpi_constants.c");
+    for variable in ["MPICC", "MPI_PKG_CONFIG", "CRAY_MPICH_DIR"] {
+        println!("cargo:rerun-if-env-changed={}", variable);
+    }
+
+    // Try to find an MPI library
+    let lib = match build_probe_mpi::probe() {
+        Ok(lib) => lib,
+        Err(errs) => {
+            println!("Could not find MPI library for various reasons:\n");
+            for (i, err) in errs.iter().enumerate() {
+                println!("Reason #{}:\n{}\n", i, err);
+            }
+            panic!();
+        }
+    };
+
+    let mut builder = cc::Build::new();
+    if let Some(mpicc) = lib.mpicc {
+        // Use `mpicc` wrapper when it exists rather than the system C compiler.
+        builder.compiler(mpicc);
+    } else {
+        for include_path in &lib.include_paths {
+            builder.include(include_path);
+        }
+    }
+
+    builder
+        .clone()
+        .file("src/mpi_constants.c")
+        .compile("bempp_mpi_constants");
+
+    match mpi_version(builder) {
+        Some(version) if version >= 4 => println!("cargo:rustc-cfg=mpi4"),
+        Some(_) => {}
+        None => {
+            println!("cargo:warning=Could not detect the MPI version. MPI-4 features are disabled.")
+        }
+    }
+}
+
+/// Return the major version of the MPI standard from `mpi.h`.
+fn mpi_version(mut builder: cc::Build) -> Option<u32> {
+    let out_dir = env::var("OUT_DIR").expect("cargo did not set OUT_DIR");
+    let source = Path::new(&out_dir).join("mpi_version.c");
+    fs::write(&source, "#include <mpi.h>\nmpi_version MPI_VERSION\n").ok()?;
+
+    let expanded = builder
+        .file(&source)
+        .cargo_metadata(false)
+        .try_expand()
+        .ok()?;
+
+    String::from_utf8_lossy(&expanded)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("mpi_version"))
+        .and_then(|version| version.trim().parse().ok())
+}
@@ -0,0 +1,67 @@
+//? mpirun -n 3
+
+//! Move data between contiguous and block-cyclic layouts.
+
+use bempp_distributed_tools::{BlockCyclicLayout, IndexLayout};
+use itertools::Itertools;
+use mpi::traits::Communicator;
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+    let rank = world.rank() as usize;
+    let size = world.size() as usize;
+
+    // A vector with 20 entries in blocks of 3 entries.
+
+    let n = 20;
+    let block_cyclic = BlockCyclicLayout::new(n, 3, &world);
+
+    for index in 0..n {
+        assert_eq!(
+            block_cyclic.rank_from_index(index),
+            Some((index / 3) % size)
+        );
+    }
+
+    let contiguous = IndexLayout::from_equidistributed_chunks(n, 1, &world);
+    let (first, last) = contiguous.local_range();
+    let data = (first..last).collect_vec();
+
+    // Each entry stores its global index, so after the remap each local value must be the
+    // global index of the corresponding local index.
+
+    let block_cyclic_data = block_cyclic.remap_from_index_layout(&contiguous, &data);
+
+    assert_eq!(
+        block_cyclic_data.len(),
+        block_cyclic.number_of_local_indices()
+    );
+    for (local_index, &value) in block_cyclic_data.iter().enumerate() {
+        assert_eq!(block_cyclic.local2global(local_index), Some(value));
+        assert_eq!(block_cyclic.global2local(rank, value), Some(local_index));
+    }
+
+    assert_eq!(
+        block_cyclic.remap_to_index_layout(&contiguous, &block_cyclic_data),
+        data
+    );
+
+    // A 7x5 matrix in 2x2 blocks on a process grid with a single column.
+
+    let matrix = BlockCyclicLayout::new_2d((7, 5), (2, 2), (size, 1), &world);
+    let contiguous = IndexLayout::from_equidistributed_chunks(35, 1, &world);
+    let (first, last) = contiguous.local_range();
+    let data = (first..last).collect_vec();
+
+    let matrix_data = matrix.remap_from_index_layout(&contiguous, &data);
+
+    for (local_index, &value) in matrix_data.iter().enumerate() {
+        assert_eq!(matrix.local2global(local_index), Some(value));
+    }
+
+    assert_eq!(
+        matrix.remap_to_index_layout(&contiguous, &matrix_data),
+        data
+    );
+}
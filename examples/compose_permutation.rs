@@ -0,0 +1,104 @@
+//? mpirun -n 2
+
+//! Invert and compose distributed permutations.
+
+use std::rc::Rc;
+
+use bempp_distributed_tools::permutation::DataPermutation;
+use bempp_distributed_tools::{Error, IndexLayout};
+use itertools::Itertools;
+use mpi::traits::Communicator;
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+
+    if world.size() != 2 {
+        println!("Please run this example with 2 MPI ranks.");
+        return;
+    }
+
+    let n = 13;
+
+    // The first permutation reverses the global order.
+
+    let index_layout = Rc::new(IndexLayout::from_equidistributed_chunks(n, 1, &world));
+    let (first, last) = index_layout.local_range();
+
+    let reverse_indices = (first..last).map(|index| n - 1 - index).collect_vec();
+    let reverse = DataPermutation::new(index_layout.clone(), &reverse_indices);
+
+    // The second permutation shifts the custom indices of the first permutation, which are
+    // numbered consecutively in the order of the ranks.
+
+    let custom_layout = Rc::new(IndexLayout::from_local_counts(
+        reverse_indices.len(),
+        &world,
+    ));
+    let (custom_first, custom_last) = custom_layout.local_range();
+
+    let shift_indices = (custom_first..custom_last)
+        .map(|index| (index + 5) % n)
+        .collect_vec();
+    let shift = DataPermutation::new(custom_layout, &shift_indices);
+
+    let data = (first..last).map(|index| 10 * index).collect_vec();
+
+    // The inverse moves data like the backward permutation.
+
+    let inverse = reverse.inverse();
+
+    let mut reversed = vec![0; reverse_indices.len()];
+    reverse.forward_permute(&data, &mut reversed, 1);
+
+    let mut expected = vec![0; data.len()];
+    reverse.backward_permute(&reversed, &mut expected, 1);
+
+    let mut actual = vec![0; data.len()];
+    inverse.forward_permute(&reversed, &mut actual, 1);
+
+    assert_eq!(actual, expected);
+    assert_eq!(actual, data);
+
+    // The composition moves data in one step as both permutations in sequence.
+
+    let composed = reverse.compose(&shift);
+
+    let mut expected = vec![0; shift_indices.len()];
+    shift.forward_permute(&reversed, &mut expected, 1);
+
+    let mut actual = vec![0; shift_indices.len()];
+    composed.forward_permute(&data, &mut actual, 1);
+
+    assert_eq!(actual, expected);
+
+    let mut restored = vec![0; data.len()];
+    composed.backward_permute(&actual, &mut restored, 1);
+
+    assert_eq!(restored, data);
+
+    // A permutation composed with its inverse is the identity.
+
+    let identity = reverse.compose(&inverse);
+
+    let mut actual = vec![0; data.len()];
+    identity.forward_permute(&data, &mut actual, 1);
+
+    assert_eq!(actual, data);
+
+    // The index layout of the second permutation must match the custom indices of the first.
+    // Here it has an additional index on rank 0. All processes return an error.
+
+    let count = reverse_indices.len() + if world.rank() == 0 { 1 } else { 0 };
+    let larger_layout = Rc::new(IndexLayout::from_local_counts(count, &world));
+    let (larger_first, larger_last) = larger_layout.local_range();
+    let larger = DataPermutation::new(larger_layout, &(larger_first..larger_last).collect_vec());
+
+    match reverse.try_compose(&larger) {
+        Err(Error::SizeMismatch { expected, actual }) => {
+            assert_eq!((world.rank(), expected, actual), (0, 7, 8))
+        }
+        Err(Error::FailedOnOtherRanks { ranks }) => assert_eq!(ranks, [0]),
+        _ => panic!("Expected an error on all processes."),
+    }
+}
@@ -1,8 +1,11 @@
+//? mpirun -n 2
+
 //! Example for using the data mapper.
 
 use std::rc::Rc;
 
-use bempp_distributed_tools::Global2LocalDataMapper;
+use bempp_distributed_tools::reduction::{Max, Sum};
+use bempp_distributed_tools::{FieldBatch, Global2LocalDataMapper};
 use itertools::Itertools;
 use mpi::traits::Communicator;
 
@@ -25,7 +28,7 @@ fn main() {
         vec![0, 1, 2, 0, 6, 9, 2, 0]
     };
 
-    let data_mapper = Global2LocalDataMapper::new(index_layout.clone(), &required_indices);
+    let mut data_mapper = Global2LocalDataMapper::new(index_layout.clone(), &required_indices);
 
     let in_vec = (0..n_indices)
         .map(|index| world.rank() as usize * n_indices + index)
@@ -34,4 +37,84 @@ fn main() {
     let out_vec = data_mapper.map_data(&in_vec, 1);
 
     assert_eq!(out_vec, required_indices);
+
+    // Mappings can write into an existing output buffer.
+
+    let mut out_vec = vec![0; required_indices.len()];
+    data_mapper.map_data_into(&in_vec, &mut out_vec, 1);
+    assert_eq!(out_vec, required_indices);
+
+    // Repeated mappings can reuse a plan with the exchange buffers.
+
+    let mut plan = data_mapper.plan(1);
+    let mut out_vec = vec![0; required_indices.len()];
+    for _ in 0..3 {
+        out_vec.fill(0);
+        data_mapper.map_data_into_with_plan(&mut plan, &in_vec, &mut out_vec);
+        assert_eq!(out_vec, required_indices);
+    }
+
+    // The same mapping with MPI derived datatypes instead of intermediate buffers.
+
+    data_mapper.use_derived_datatypes(true);
+
+    let out_vec = data_mapper.map_data(&in_vec, 1);
+
+    assert_eq!(out_vec, required_indices);
+
+    let mut out_vec = vec![0; required_indices.len()];
+    data_mapper.map_data_into(&in_vec, &mut out_vec, 1);
+    assert_eq!(out_vec, required_indices);
+
+    // Plans have to be created again after switching to derived datatypes.
+
+    let mut plan = data_mapper.plan(1);
+    let mut out_vec = vec![0; required_indices.len()];
+    for _ in 0..3 {
+        out_vec.fill(0);
+        data_mapper.map_data_into_with_plan(&mut plan, &in_vec, &mut out_vec);
+        assert_eq!(out_vec, required_indices);
+    }
+
+    // Several fields with different types and chunk sizes can be mapped in one exchange.
+
+    let coordinates = in_vec
+        .iter()
+        .flat_map(|&index| [index as f64, 2.0 * index as f64, 3.0 * index as f64])
+        .collect_vec();
+
+    let mut out_indices = vec![0; required_indices.len()];
+    let mut out_coordinates = vec![0.0; 3 * required_indices.len()];
+
+    let mut batch = FieldBatch::new();
+    batch
+        .add(&in_vec, &mut out_indices, 1)
+        .add(&coordinates, &mut out_coordinates, 3);
+    data_mapper.map_data_batch(batch);
+
+    assert_eq!(out_indices, required_indices);
+    assert_eq!(out_coordinates, data_mapper.map_data(&coordinates, 3));
+
+    // Sum up contributions of all processes for each dof on the owning process. Dofs that are
+    // not required by any process are zero.
+
+    let contributions = vec![1; required_indices.len()];
+    let counts = data_mapper.reverse_map_data(&contributions, 1, Sum);
+
+    if world.rank() == 0 {
+        assert_eq!(counts, [4, 3, 4, 1, 0]);
+    } else {
+        assert_eq!(counts, [1, 2, 0, 0, 2]);
+    }
+
+    // Other reductions work the same way.
+
+    let contributions = vec![world.rank(); required_indices.len()];
+    let maxima = data_mapper.reverse_map_data(&contributions, 1, Max);
+
+    if world.rank() == 0 {
+        assert_eq!(maxima, [1, 1, 1, 0, 0]);
+    } else {
+        assert_eq!(maxima, [0, 1, 0, 0, 1]);
+    }
 }
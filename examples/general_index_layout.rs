@@ -0,0 +1,81 @@
+//? mpirun -n 3
+
+//! Index layouts with arbitrary ownership.
+//!
+//! The indices `0..30` are distributed round robin, i.e. each process owns the indices
+//! `rank, rank + size, rank + 2 * size, ...`.
+
+use std::rc::Rc;
+
+use bempp_distributed_tools::{
+    DataPermutation, GeneralIndexLayout, GhostedVector, Global2LocalDataMapper,
+};
+use itertools::Itertools;
+use mpi::traits::Communicator;
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+    let rank = world.rank() as usize;
+    let size = world.size() as usize;
+
+    let n = 30;
+
+    let owned_indices = (rank..n).step_by(size).collect_vec();
+    let index_layout = Rc::new(GeneralIndexLayout::new(owned_indices.clone(), &world));
+
+    assert_eq!(index_layout.number_of_global_indices(), n);
+    assert_eq!(index_layout.local2global(1), Some(rank + size));
+
+    // Owner queries go to the rendezvous processes and are collective.
+
+    let queries = (0..n).rev().collect_vec();
+    let ranks = index_layout.ranks_from_indices(&queries);
+    for (&index, &owner) in queries.iter().zip(ranks.iter()) {
+        assert_eq!(owner, index % size);
+    }
+
+    // The owning ranks and local indices can be queried together. Out of bounds indices
+    // have no location.
+
+    let locations = index_layout.locations_from_indices(&[2, n, size + 1]);
+    assert_eq!(locations, [Some((2 % size, 2 / size)), None, Some((1, 1))]);
+
+    // Single indices can be queried as well. Each query is a collective operation.
+
+    assert_eq!(index_layout.rank_from_index(2), Some(2 % size));
+    assert_eq!(index_layout.rank_from_index(n), None);
+    assert_eq!(index_layout.global2local(2 % size, 2), Some(2 / size));
+    assert_eq!(index_layout.global2local(n % size, n), None);
+
+    // Every process requires the first ten indices.
+
+    let required_indices = (0..10).collect_vec();
+    let data_mapper = Global2LocalDataMapper::new(index_layout.clone(), &required_indices);
+
+    let mapped_data = data_mapper.map_data(&owned_indices, 1);
+    assert_eq!(mapped_data, required_indices);
+
+    // Permute the data to the contiguous block layout.
+
+    let first = rank * n / size;
+    let last = (rank + 1) * n / size;
+    let custom_indices = (first..last).collect_vec();
+
+    let permutation = DataPermutation::new(index_layout.clone(), &custom_indices);
+
+    let mut permuted_data = vec![0; custom_indices.len()];
+    permutation.forward_permute(&owned_indices, &mut permuted_data, 1);
+
+    assert_eq!(permuted_data, custom_indices);
+
+    // A ghosted vector over the same layout.
+
+    let mut vector = GhostedVector::<usize, _, _>::new(index_layout.clone(), &required_indices, 1);
+    vector.owned_mut().copy_from_slice(&owned_indices);
+    vector.update_ghosts();
+
+    for &index in &required_indices {
+        assert_eq!(vector.get(index).unwrap(), &[index]);
+    }
+}
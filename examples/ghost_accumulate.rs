@@ -0,0 +1,63 @@
+//? mpirun -n 3
+
+//! Accumulate ghost contributions onto their owners.
+//!
+//! Each process owns the indices `5 * rank..5 * (rank + 1)`. All processes apart from
+//! process 0 hold a ghost of index 0 and contribute the value `rank` to it. The contributions
+//! are summed up on process 0.
+
+use bempp_distributed_tools::reduction::{Max, Sum};
+use bempp_distributed_tools::{GhostCommunicator, IndexLayout};
+use mpi::traits::Communicator;
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+    let rank = world.rank() as usize;
+    let size = world.size() as usize;
+
+    let ghost_comm = if rank == 0 {
+        GhostCommunicator::new(&[], &[], &world)
+    } else {
+        GhostCommunicator::new(&[0], &[0], &world)
+    };
+
+    let ghost_values = vec![rank; ghost_comm.total_receive_count()];
+
+    // Owned values are stored relative to the first owned index.
+
+    let mut owned_values = vec![1; 5];
+    ghost_comm.backward_accumulate_with(Sum, &ghost_values, &mut owned_values, 1, |index| {
+        index - 5 * rank
+    });
+
+    if rank == 0 {
+        assert_eq!(owned_values[0], 1 + (1..size).sum::<usize>());
+    }
+
+    let mut owned_values = vec![0; 5];
+    ghost_comm.backward_accumulate_with(Max, &ghost_values, &mut owned_values, 1, |index| {
+        index - 5 * rank
+    });
+
+    if rank == 0 {
+        assert_eq!(owned_values[0], size - 1);
+    }
+
+    // For ghost communicators created from an index layout the positions of the owned values
+    // are known. Index 0 is owned by process 0 and dropped from its ghosts.
+
+    let index_layout = IndexLayout::from_local_counts(5, &world);
+    let layout_comm = GhostCommunicator::from_layout(&index_layout, &[0], true);
+
+    let ghost_values = vec![rank; layout_comm.total_receive_count()];
+
+    let mut owned_values = vec![1; 5];
+    layout_comm.backward_accumulate(Sum, &ghost_values, &mut owned_values, 1);
+
+    if rank == 0 {
+        assert_eq!(owned_values, [1 + (1..size).sum::<usize>(), 1, 1, 1, 1]);
+    } else {
+        assert_eq!(owned_values, [1; 5]);
+    }
+}
@@ -10,7 +10,10 @@
 //! The second process requires index 4 from the third process as ghost index.
 //! The third process requires indices 0, 1, 2 from the first process as ghost.
 
-use bempp_distributed_tools::GhostCommunicator;
+use bempp_distributed_tools::{
+    Error, GhostCommunicator, GhostCommunicatorBackend, GhostCommunicatorOptions,
+    GhostCommunicatorSetup, IndexLayout,
+};
 use mpi::traits::Communicator;
 
 pub fn main() {
@@ -101,4 +104,191 @@ pub fn main() {
     } else {
         assert_eq!(send_data[0], 15);
     }
+
+    // The same exchanges can be started without blocking. The requests are registered with
+    // a scope and the buffers stay borrowed until the requests have completed.
+
+    let mut received_data_nonblocking = vec![0; ghost_comm.total_receive_count()];
+    let mut send_data_nonblocking = vec![0; ghost_comm.total_send_count()];
+
+    mpi::request::scope(|scope| {
+        let request = ghost_comm.forward_send_values_nonblocking(
+            scope,
+            &data,
+            &mut received_data_nonblocking,
+        );
+
+        // Work that does not touch the buffers can overlap with the communication here.
+
+        request.wait();
+    });
+
+    assert_eq!(received_data, received_data_nonblocking);
+
+    mpi::request::scope(|scope| {
+        let mut request = ghost_comm.backward_send_values_nonblocking(
+            scope,
+            &received_data_nonblocking,
+            &mut send_data_nonblocking,
+        );
+
+        // Poll the exchange until it has completed.
+
+        loop {
+            match request.test() {
+                Ok(()) => break,
+                Err(pending) => request = pending,
+            }
+        }
+    });
+
+    assert_eq!(send_data, send_data_nonblocking);
+
+    // Chunks of values are exchanged in the same way.
+
+    let chunked_data = data
+        .iter()
+        .flat_map(|&value| [value, 2 * value])
+        .collect::<Vec<_>>();
+    let mut chunked_received_data = vec![0; 2 * ghost_comm.total_receive_count()];
+    let mut chunked_send_data = vec![0; 2 * ghost_comm.total_send_count()];
+
+    mpi::request::scope(|scope| {
+        ghost_comm
+            .forward_send_values_by_chunks_nonblocking(
+                scope,
+                &chunked_data,
+                &mut chunked_received_data,
+                2,
+            )
+            .wait();
+    });
+
+    for (chunk, &value) in chunked_received_data.chunks(2).zip(&received_data) {
+        assert_eq!(chunk, [value, 2 * value]);
+    }
+
+    mpi::request::scope(|scope| {
+        ghost_comm
+            .backward_send_values_by_chunks_nonblocking(
+                scope,
+                &chunked_received_data,
+                &mut chunked_send_data,
+                2,
+            )
+            .wait();
+    });
+
+    for (chunk, &value) in chunked_send_data.chunks(2).zip(&send_data) {
+        assert_eq!(chunk, [value, 2 * value]);
+    }
+
+    // A ghost communicator with the point-to-point backend gives the same results.
+
+    let options = GhostCommunicatorOptions {
+        backend: GhostCommunicatorBackend::PointToPoint,
+        ..Default::default()
+    };
+
+    let point_to_point_comm = if rank == 0 {
+        GhostCommunicator::new_with_options(&[5, 6], &[1, 1], &world, options)
+    } else if rank == 1 {
+        GhostCommunicator::new_with_options(&[10], &[2], &world, options)
+    } else {
+        GhostCommunicator::new_with_options(&[5, 0, 1, 2], &[1, 0, 0, 0], &world, options)
+    };
+
+    assert_eq!(point_to_point_comm.send_indices, ghost_comm.send_indices);
+
+    let mut received_data_point_to_point = vec![0; point_to_point_comm.total_receive_count()];
+    point_to_point_comm.forward_send_values(&data, &mut received_data_point_to_point);
+
+    assert_eq!(received_data, received_data_point_to_point);
+
+    // The sparse consensus setup discovers the same communication pattern without a dense
+    // all-to-all exchange.
+
+    let options = GhostCommunicatorOptions {
+        setup: GhostCommunicatorSetup::SparseConsensus,
+        ..Default::default()
+    };
+
+    let sparse_comm = if rank == 0 {
+        GhostCommunicator::new_with_options(&[5, 6], &[1, 1], &world, options)
+    } else if rank == 1 {
+        GhostCommunicator::new_with_options(&[10], &[2], &world, options)
+    } else {
+        GhostCommunicator::new_with_options(&[5, 0, 1, 2], &[1, 0, 0, 0], &world, options)
+    };
+
+    assert_eq!(sparse_comm.out_ranks, ghost_comm.out_ranks);
+    assert_eq!(sparse_comm.send_counts, ghost_comm.send_counts);
+    assert_eq!(sparse_comm.send_indices, ghost_comm.send_indices);
+
+    // With edge weights and reordering MPI may suggest new ranks for the processes. The
+    // exchanges still use the original ranks and connect the same processes.
+
+    let options = GhostCommunicatorOptions {
+        weighted: true,
+        reorder: true,
+        ..Default::default()
+    };
+
+    let reordered_comm = if rank == 0 {
+        GhostCommunicator::new_with_options(&[5, 6], &[1, 1], &world, options)
+    } else if rank == 1 {
+        GhostCommunicator::new_with_options(&[10], &[2], &world, options)
+    } else {
+        GhostCommunicator::new_with_options(&[5, 0, 1, 2], &[1, 0, 0, 0], &world, options)
+    };
+
+    let mut rank_permutation = reordered_comm.rank_permutation().unwrap().to_vec();
+    rank_permutation.sort_unstable();
+    assert_eq!(rank_permutation, [0, 1, 2]);
+    assert_eq!(reordered_comm.forward_comm().rank(), rank);
+
+    let mut received_data_reordered = vec![0; reordered_comm.total_receive_count()];
+    reordered_comm.forward_send_values(&data, &mut received_data_reordered);
+
+    assert_eq!(received_data, received_data_reordered);
+
+    // Ghost communicators can also be created from an index layout, which provides the owners
+    // of the ghosts. Ghosts owned by the current process are an error unless they are dropped.
+    // If the check fails on one process, all processes return an error.
+
+    let index_layout = IndexLayout::from_equidistributed_chunks(15, 1, &world);
+    let ghost_indices = [5 * ((rank as usize + 1) % 3), 5 * rank as usize];
+
+    let layout_comm = GhostCommunicator::from_layout(&index_layout, &ghost_indices, true);
+    assert_eq!(layout_comm.receive_indices, [ghost_indices[0]]);
+
+    let result = GhostCommunicator::try_from_layout(
+        &index_layout,
+        if rank == 0 {
+            &ghost_indices[..]
+        } else {
+            &ghost_indices[..1]
+        },
+        false,
+    );
+    match result {
+        Err(Error::OwnedGhostIndex { index }) => assert_eq!((rank, index), (0, 0)),
+        Err(Error::FailedOnOtherRanks { ranks }) => assert_eq!(ranks, [0]),
+        _ => panic!("Expected an error on all processes."),
+    }
+
+    // The same holds for ghost indices without an owner.
+
+    let result = GhostCommunicator::try_from_layout(
+        &index_layout,
+        if rank == 1 { &[3, 15][..] } else { &[3][..] },
+        true,
+    );
+    match result {
+        Err(Error::IndexOutOfRange { index, bound }) => {
+            assert_eq!((rank, index, bound), (1, 15, 15))
+        }
+        Err(Error::FailedOnOtherRanks { ranks }) => assert_eq!(ranks, [1]),
+        _ => panic!("Expected an error on all processes."),
+    }
 }
@@ -0,0 +1,63 @@
+//? mpirun -n 3
+
+//! Ghost updates and accumulation with a ghosted vector.
+//!
+//! We distribute a one dimensional grid of points among the processes. Each process requires
+//! the points directly left and right of its own points as ghosts.
+
+use std::rc::Rc;
+
+use bempp_distributed_tools::reduction::Sum;
+use bempp_distributed_tools::{GhostedVector, IndexLayout};
+use mpi::traits::Communicator;
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+
+    let npoints = 30;
+    let chunk_size = 2;
+
+    let index_layout = Rc::new(IndexLayout::from_equidistributed_chunks(npoints, 1, &world));
+    let (first, last) = index_layout.local_range();
+
+    let mut ghosts = Vec::new();
+    if first > 0 {
+        ghosts.push(first - 1);
+    }
+    if last < npoints {
+        ghosts.push(last);
+    }
+
+    let mut vector = GhostedVector::<usize, _>::new(index_layout.clone(), &ghosts, chunk_size);
+
+    // Each point stores its global index twice.
+
+    for (index, chunk) in (first..last).zip(vector.owned_mut().chunks_mut(chunk_size)) {
+        chunk.fill(index);
+    }
+
+    vector.update_ghosts();
+
+    for &ghost in &ghosts {
+        assert_eq!(vector.get(ghost).unwrap(), &[ghost, ghost]);
+    }
+
+    // Every process now adds one to each of its ghosts and accumulates the
+    // contributions onto the owners.
+
+    vector.ghosts_mut().fill(1);
+    vector.owned_mut().fill(0);
+    vector.accumulate_ghosts(Sum);
+
+    // Each point is a ghost on at most one other process.
+
+    if world.size() > 1 {
+        if first > 0 {
+            assert_eq!(vector.get(first).unwrap(), &[1, 1]);
+        }
+        if last < npoints {
+            assert_eq!(vector.get(last - 1).unwrap(), &[1, 1]);
+        }
+    }
+}
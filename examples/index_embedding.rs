@@ -0,0 +1,90 @@
+//? mpirun -n 2
+
+//! Embed data on a subset of the indices into the full index layout and extract it again.
+
+use std::rc::Rc;
+
+use bempp_distributed_tools::index_embedding::IndexEmbedding;
+use bempp_distributed_tools::{Error, IndexLayout};
+use itertools::Itertools;
+use mpi::traits::Communicator;
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+
+    if world.size() != 2 {
+        println!("Please run this example with 2 MPI ranks.");
+        return;
+    }
+
+    // A layout with 10 indices on each rank, of which every third local index is embedded.
+
+    let global_layout = Rc::new(IndexLayout::from_equidistributed_chunks(20, 1, &world));
+    let (first, _) = global_layout.local_range();
+
+    let embedded_indices = [0, 3, 6, 9];
+    let embedding = IndexEmbedding::new(global_layout.clone(), &embedded_indices, &world);
+
+    assert_eq!(embedding.embedded_layout().number_of_global_indices(), 8);
+
+    // The embedded data has two values per embedded index.
+
+    let data = embedded_indices
+        .iter()
+        .flat_map(|&index| [first + index, first + index])
+        .collect_vec();
+
+    let mut embedded_data = vec![0; 20];
+    embedding.embed_data(&data, &mut embedded_data, 2);
+
+    for (local_index, chunk) in embedded_data.chunks(2).enumerate() {
+        if local_index % 3 == 0 {
+            assert_eq!(chunk, [first + local_index, first + local_index]);
+        } else {
+            assert_eq!(chunk, [0, 0]);
+        }
+    }
+
+    assert_eq!(embedding.extract_embedded_data(&embedded_data, 2), data);
+
+    // Invalid arguments can be handled as errors instead of panics. The output is left
+    // untouched in case of an error.
+
+    let mut out_vector = vec![1; 10];
+    assert_eq!(
+        embedding.try_embed_data(&data, &mut out_vector, 2),
+        Err(Error::SizeMismatch {
+            expected: 20,
+            actual: 10
+        })
+    );
+    assert_eq!(out_vector, [1; 10]);
+
+    assert_eq!(
+        embedding.try_extract_embedded_data(&embedded_data[..10], 2),
+        Err(Error::SizeMismatch {
+            expected: 20,
+            actual: 10
+        })
+    );
+
+    // The embedded indices must be local indices of the global layout.
+
+    let invalid_embedding = IndexEmbedding::new(global_layout, &[2, 10], &world);
+
+    assert_eq!(
+        invalid_embedding.try_extract_embedded_data(&embedded_data, 2),
+        Err(Error::IndexOutOfRange {
+            index: 10,
+            bound: 10
+        })
+    );
+    assert_eq!(
+        invalid_embedding.try_embed_data(&[0; 4], &mut embedded_data, 2),
+        Err(Error::IndexOutOfRange {
+            index: 10,
+            bound: 10
+        })
+    );
+}
@@ -3,6 +3,7 @@
 //! Map betwen two index layouts
 
 use bempp_distributed_tools::index_layout::IndexLayout;
+use bempp_distributed_tools::{Error, LayoutRemap};
 use itertools::{izip, Itertools};
 use mpi::traits::Communicator;
 
@@ -62,4 +63,98 @@ fn main() {
     let remapped_data = layout2.remap(&layout1, &mapped_data);
 
     assert_eq!(data, remapped_data);
+
+    // Remap three values per index, e.g. point coordinates.
+
+    let coordinates = data
+        .iter()
+        .flat_map(|&index| [index, 100 + index, 200 + index])
+        .collect_vec();
+
+    let mapped_coordinates = layout1.remap_by_chunks(&layout2, &coordinates, 3);
+    let expected = mapped_data
+        .iter()
+        .flat_map(|&index| [index, 100 + index, 200 + index])
+        .collect_vec();
+    assert_eq!(mapped_coordinates, expected);
+
+    // For repeated remaps between the same layouts, precompute the remap and write into
+    // existing buffers.
+
+    let remap = LayoutRemap::new(&layout1, &layout2);
+    let mut out_data = vec![0; 3 * layout2.number_of_local_indices()];
+
+    remap.remap_into(&coordinates, &mut out_data, 3);
+    assert_eq!(out_data, expected);
+
+    out_data.fill(0);
+    mpi::request::scope(|scope| {
+        let request = remap.remap_into_nonblocking(scope, &coordinates, &mut out_data, 3);
+        request.wait();
+    });
+    assert_eq!(out_data, expected);
+
+    // Invalid arguments can be handled as errors instead of panics.
+
+    assert_eq!(
+        layout1.try_remap(&layout2, &data[..5]),
+        Err(Error::SizeMismatch {
+            expected: 10,
+            actual: 5
+        })
+    );
+
+    // If the arguments are only invalid on one rank, the other ranks return an error as well
+    // instead of waiting for it.
+
+    let length = if world.rank() == 0 { 5 } else { 10 };
+    let result = layout1.try_remap(&layout2, &data[..length]);
+    if world.rank() == 0 {
+        assert_eq!(
+            result,
+            Err(Error::SizeMismatch {
+                expected: 10,
+                actual: 5
+            })
+        );
+    } else {
+        assert_eq!(result, Err(Error::FailedOnOtherRanks { ranks: vec![0] }));
+    }
+
+    // Rank lookups skip ranks without indices. Here rank 1 has no indices.
+
+    let layout3 = IndexLayout::from_local_counts(if world.rank() == 1 { 0 } else { 15 }, &world);
+
+    assert_eq!(layout3.rank_from_index(14), Some(0));
+    assert_eq!(layout3.rank_from_index(15), Some(2));
+    assert_eq!(layout3.rank_from_index(30), None);
+
+    assert_eq!(layout3.ranks_from_indices(&[0, 14, 15, 29]), [0, 0, 2, 2]);
+    assert_eq!(layout3.ranks_from_indices(&[29, 3, 15, 0]), [2, 0, 2, 0]);
+
+    // Several indices can be converted at once. Indices that do not exist on the rank, are
+    // out of bounds or refer to an invalid rank are `None`.
+
+    assert_eq!(
+        layout1.global2local_many(1, &[12, 5, 19, 20, 30]),
+        [Some(2), None, Some(9), None, None]
+    );
+    assert_eq!(layout3.global2local_many(1, &[0, 15]), [None, None]);
+    assert_eq!(layout1.global2local_many(3, &[0, 29]), [None, None]);
+
+    let local2global = layout1.local2global_many(&[0, 9, 10]);
+    let first = 10 * world.rank() as usize;
+    assert_eq!(local2global, [Some(first), Some(first + 9), None]);
+
+    // Layouts can be checked for consistency across the ranks.
+
+    assert_eq!(layout3.validate(), Ok(()));
+
+    let counts = if world.rank() == 2 {
+        vec![0, 10, 20, 31]
+    } else {
+        vec![0, 10, 20, 30]
+    };
+    let inconsistent = IndexLayout::new(counts, &world);
+    assert_eq!(inconsistent.validate(), Err(Error::InconsistentLayout));
 }
@@ -3,7 +3,7 @@
 use std::rc::Rc;
 
 use bempp_distributed_tools::permutation::DataPermutation;
-use bempp_distributed_tools::IndexLayout;
+use bempp_distributed_tools::{FieldBatch, IndexLayout};
 use itertools::{izip, Itertools};
 use rand::prelude::*;
 use rand::seq::SliceRandom;
@@ -29,7 +29,7 @@ fn main() {
 
     let custom_indices = &custom_global_layout[local_bounds.0..local_bounds.1];
 
-    let permutation = DataPermutation::new(index_layout.clone(), custom_indices);
+    let mut permutation = DataPermutation::new(index_layout.clone(), custom_indices);
 
     // We now want to send some data over.
 
@@ -51,4 +51,56 @@ fn main() {
     for (&actual, &expected) in izip!(permuted_backward_data.iter(), data.iter()) {
         assert_eq!(actual, expected);
     }
+
+    // With derived datatypes the permutations give the same results.
+
+    permutation.use_derived_datatypes(true);
+
+    let mut permuted_forward_data_datatypes = vec![0; chunk_size * custom_indices.len()];
+    permutation.forward_permute(&data, &mut permuted_forward_data_datatypes, chunk_size);
+
+    assert_eq!(permuted_forward_data_datatypes, permuted_forward_data);
+
+    permuted_backward_data.fill(0);
+    permutation.backward_permute(
+        &permuted_forward_data_datatypes,
+        &mut permuted_backward_data,
+        chunk_size,
+    );
+
+    assert_eq!(permuted_backward_data, data);
+
+    // Several fields can be permuted in one exchange.
+
+    let weights = (local_bounds.0..local_bounds.1)
+        .map(|elem| elem as f64)
+        .collect_vec();
+
+    let mut batch_forward_data = vec![0; chunk_size * custom_indices.len()];
+    let mut batch_forward_weights = vec![0.0; custom_indices.len()];
+
+    let mut batch = FieldBatch::new();
+    batch.add(&data, &mut batch_forward_data, chunk_size).add(
+        &weights,
+        &mut batch_forward_weights,
+        1,
+    );
+    permutation.forward_permute_batch(batch);
+
+    assert_eq!(batch_forward_data, permuted_forward_data);
+    for (&weight, &index) in izip!(&batch_forward_weights, custom_indices) {
+        assert_eq!(weight, index as f64);
+    }
+
+    let mut batch_backward_data = vec![0; chunk_size * index_layout.number_of_local_indices()];
+    let mut batch_backward_weights = vec![0.0; index_layout.number_of_local_indices()];
+
+    let mut batch = FieldBatch::new();
+    batch
+        .add(&batch_forward_data, &mut batch_backward_data, chunk_size)
+        .add(&batch_forward_weights, &mut batch_backward_weights, 1);
+    permutation.backward_permute_batch(batch);
+
+    assert_eq!(batch_backward_data, data);
+    assert_eq!(batch_backward_weights, weights);
 }
@@ -0,0 +1,75 @@
+//? mpirun -n 3
+
+//! Repeated ghost updates with a persistent exchange.
+//!
+//! Each process owns the indices `5 * rank..5 * (rank + 1)` and requires the first index
+//! of the next process as ghost. The exchanges are set up once and then repeated for a
+//! number of time steps.
+
+use bempp_distributed_tools::{GhostCommunicator, PersistentExchange};
+use mpi::traits::Communicator;
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+    let rank = world.rank() as usize;
+    let size = world.size() as usize;
+
+    let next = (rank + 1) % size;
+
+    let ghost_comm = GhostCommunicator::new(&[5 * next], &[next], &world);
+
+    let mut send_values = vec![0; ghost_comm.total_send_count()];
+    let mut receive_values = vec![0; ghost_comm.total_receive_count()];
+
+    mpi::request::scope(|scope| {
+        let mut exchange = PersistentExchange::forward(
+            scope,
+            &ghost_comm,
+            &mut send_values,
+            &mut receive_values,
+            1,
+        );
+
+        for step in 0..10 {
+            // Every process sends the value of its first index for the current time step.
+            for value in exchange.send_buffer_mut().iter_mut() {
+                *value = 100 * step + rank;
+            }
+
+            exchange.start();
+            exchange.wait();
+
+            assert_eq!(exchange.receive_buffer(), &[100 * step + next]);
+        }
+    });
+
+    // Updates of the ghosts can be sent back to the owners in the same way.
+
+    let previous = (rank + size - 1) % size;
+
+    let mut ghost_updates = vec![0; ghost_comm.total_receive_count()];
+    let mut owner_values = vec![0; ghost_comm.total_send_count()];
+
+    mpi::request::scope(|scope| {
+        let mut exchange = PersistentExchange::backward(
+            scope,
+            &ghost_comm,
+            &mut ghost_updates,
+            &mut owner_values,
+            1,
+        );
+
+        for step in 0..10 {
+            // Every process sends an update of its ghost back to the next process.
+            for value in exchange.send_buffer_mut().iter_mut() {
+                *value = 100 * step + rank;
+            }
+
+            exchange.start();
+            exchange.wait();
+
+            assert_eq!(exchange.receive_buffer(), &[100 * step + previous]);
+        }
+    });
+}
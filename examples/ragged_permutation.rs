@@ -0,0 +1,76 @@
+//? mpirun -n 2
+
+//! Permute data in place and data with a variable number of values per index.
+
+use std::rc::Rc;
+
+use bempp_distributed_tools::permutation::DataPermutation;
+use bempp_distributed_tools::IndexLayout;
+use itertools::{izip, Itertools};
+use mpi::traits::Communicator;
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+
+    if world.size() != 2 {
+        println!("Please run this example with 2 MPI ranks.");
+        return;
+    }
+
+    let n = 11;
+
+    // The custom indices reverse the global order, so that most indices change the process.
+
+    let index_layout = Rc::new(IndexLayout::from_equidistributed_chunks(n, 1, &world));
+    let (first, last) = index_layout.local_range();
+
+    let custom_indices = (first..last).map(|index| n - 1 - index).collect_vec();
+
+    let permutation = DataPermutation::new(index_layout.clone(), &custom_indices);
+
+    // The local indices and the custom indices have the same count on each process, so the
+    // data can be permuted in place.
+
+    let data = (first..last).flat_map(|index| [index, index]).collect_vec();
+
+    let mut values = data.clone();
+    permutation.forward_permute_in_place(&mut values, 2);
+
+    for (chunk, &index) in izip!(values.chunks(2), &custom_indices) {
+        assert_eq!(chunk, [index, index]);
+    }
+
+    permutation.backward_permute_in_place(&mut values, 2);
+
+    assert_eq!(values, data);
+
+    // Each index has `index % 3` values, all equal to the index.
+
+    let lengths = (first..last).map(|index| index % 3).collect_vec();
+    let offsets = std::iter::once(0)
+        .chain(lengths.iter().scan(0, |acc, &length| {
+            *acc += length;
+            Some(*acc)
+        }))
+        .collect_vec();
+    let ragged_data = (first..last)
+        .flat_map(|index| vec![index; index % 3])
+        .collect_vec();
+
+    let (permuted_offsets, permuted_data) =
+        permutation.forward_permute_ragged(&ragged_data, &offsets);
+
+    assert_eq!(permuted_offsets.len(), 1 + custom_indices.len());
+    for (pos, &index) in custom_indices.iter().enumerate() {
+        let values = &permuted_data[permuted_offsets[pos]..permuted_offsets[1 + pos]];
+        assert_eq!(values.len(), index % 3);
+        assert!(values.iter().all(|&value| value == index));
+    }
+
+    let (original_offsets, original_data) =
+        permutation.backward_permute_ragged(&permuted_data, &permuted_offsets);
+
+    assert_eq!(original_offsets, offsets);
+    assert_eq!(original_data, ragged_data);
+}
@@ -0,0 +1,55 @@
+//? mpirun -n 2
+
+//! Store index layouts and data mappers without borrowing the communicator.
+
+use std::rc::Rc;
+
+use bempp_distributed_tools::{CommRef, Global2LocalDataMapper, IndexLayout};
+use itertools::Itertools;
+use mpi::topology::SimpleCommunicator;
+use mpi::traits::Communicator;
+
+/// A long-lived struct that stores a data mapper.
+struct Gather {
+    mapper: Global2LocalDataMapper<'static, SimpleCommunicator>,
+}
+
+/// Create the data mapper in a function and return it.
+fn setup(comm: &SimpleCommunicator, required_dofs: &[usize]) -> Gather {
+    // The layout owns a duplicate of the communicator.
+
+    let index_layout = Rc::new(IndexLayout::from_local_counts(5, CommRef::duplicate(comm)));
+
+    Gather {
+        mapper: Global2LocalDataMapper::new(index_layout, required_dofs),
+    }
+}
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+
+    assert_eq!(world.size(), 2, "This example must be run using two ranks.");
+
+    let required_dofs = if world.rank() == 0 {
+        vec![0, 7, 2, 9]
+    } else {
+        vec![1, 4, 8]
+    };
+
+    let gather = setup(&world, &required_dofs);
+
+    let data = (0..5)
+        .map(|index| 5 * world.rank() as usize + index)
+        .collect_vec();
+
+    assert_eq!(gather.mapper.map_data(&data, 1), required_dofs);
+
+    // Further layouts can share the communicator of an existing layout.
+
+    let comm = gather.mapper.index_layout().comm_ref().clone();
+    assert!(comm.is_shared());
+
+    let layout = IndexLayout::from_local_counts(3, comm);
+    assert_eq!(layout.number_of_global_indices(), 6);
+}
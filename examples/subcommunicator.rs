@@ -0,0 +1,66 @@
+//? mpirun -n 3
+
+//! Restrict an index layout to a sub-communicator and extend it back.
+
+use bempp_distributed_tools::IndexLayout;
+use itertools::Itertools;
+use mpi::topology::Color;
+use mpi::traits::Communicator;
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+
+    if world.size() != 3 {
+        println!("Please run this example with 3 MPI ranks.");
+        return;
+    }
+
+    let rank = world.rank() as usize;
+
+    // A layout with 10 indices on each rank. The value of each index is the index itself.
+
+    let layout = IndexLayout::from_equidistributed_chunks(30, 1, &world);
+    let data = (10 * rank..10 * (rank + 1)).collect_vec();
+
+    // Only the ranks 1 and 2 take part in the coarse phase.
+
+    let color = if rank == 0 {
+        Color::undefined()
+    } else {
+        Color::with_value(0)
+    };
+    let subcomm = world.split_by_color(color);
+
+    let (coarse_layout, mover) = layout.restrict_to(subcomm.as_ref());
+
+    // Gather two values per index onto the coarse ranks.
+
+    let pairs = data
+        .iter()
+        .flat_map(|&index| [index, 2 * index])
+        .collect_vec();
+    let coarse_data = mover.forward(&pairs, 2);
+
+    if let Some(coarse_layout) = &coarse_layout {
+        assert_eq!(coarse_layout.number_of_local_indices(), 15);
+        let (first, last) = coarse_layout.local_range();
+        let expected = (first..last)
+            .flat_map(|index| [index, 2 * index])
+            .collect_vec();
+        assert_eq!(coarse_data, expected);
+    } else {
+        assert!(coarse_data.is_empty());
+    }
+
+    // Move the data back to the original layout.
+
+    assert_eq!(mover.backward(&coarse_data, 2), pairs);
+
+    // Alternatively, create a new layout on the parent communicator from the coarse layout.
+
+    let (extended_layout, mover) = IndexLayout::extend_to(coarse_layout.as_ref(), &world);
+
+    assert_eq!(extended_layout.number_of_global_indices(), 30);
+    assert_eq!(mover.forward(&coarse_data, 2), pairs);
+}
@@ -0,0 +1,49 @@
+//? mpirun -n 3
+
+//! Balance an index layout by the weights of the indices.
+
+use bempp_distributed_tools::{BalancedLayout, IndexLayout};
+use itertools::Itertools;
+use mpi::traits::Communicator;
+
+fn main() {
+    let universe = mpi::initialize().unwrap();
+    let world = universe.world();
+
+    if world.size() != 3 {
+        println!("Please run this example with 3 MPI ranks.");
+        return;
+    }
+
+    // Each rank currently holds 10 indices. The indices on rank 0 are ten times as
+    // expensive as all other indices.
+
+    let rank = world.rank() as usize;
+    let weights = vec![if rank == 0 { 10.0 } else { 1.0 }; 10];
+
+    let BalancedLayout {
+        layout: balanced,
+        remap,
+    } = IndexLayout::from_local_weights(&weights, &world);
+
+    assert_eq!(remap.number_of_source_indices(), 10);
+    assert_eq!(balanced.number_of_global_indices(), 30);
+
+    // The total weight is 120, so each rank should get a weight of 40. Rank 0 keeps its first
+    // 4 indices, rank 1 the next 4 indices and rank 2 the remaining 22 indices.
+
+    assert_eq!(balanced.counts(), [0, 4, 8, 30]);
+
+    // Move the data to the balanced layout.
+
+    let data = (10 * rank..10 * (rank + 1)).collect_vec();
+    let balanced_data = remap.remap(&data, 1);
+
+    let (first, last) = balanced.local_range();
+    assert_eq!(balanced_data, (first..last).collect_vec());
+
+    // Without weights the indices are distributed by count.
+
+    let balanced = IndexLayout::from_local_weights(&[0.0; 10], &world);
+    assert_eq!(balanced.layout.counts(), [0, 10, 20, 30]);
+}
@@ -0,0 +1,363 @@
+//! Block-cyclic index layouts.
+//!
+//! A [BlockCyclicLayout] distributes the entries of a dense matrix in the same way as
+//! ScaLAPACK. The matrix is split into blocks, which are assigned cyclically to the processes
+//! of a two dimensional process grid. Process `(p, q)` of the grid has rank
+//! `p * grid_columns + q`. The global index of the matrix entry `(i, j)` is `i + j * rows`
+//! and the local entries are stored in column-major order as well.
+//!
+//! A one dimensional block-cyclic distribution of a vector is the special case of a matrix
+//! with a single column on a process grid with a single column.
+//!
+//! Data can be moved between a block-cyclic layout and a contiguous [IndexLayout] with
+//! [BlockCyclicLayout::remap_to_index_layout] and [BlockCyclicLayout::remap_from_index_layout].
+
+use mpi::traits::{Communicator, Equivalence};
+
+use crate::comm_ref::CommRef;
+use crate::error::Error;
+use crate::layout::{remap_data, Layout};
+use crate::IndexLayout;
+
+/// A block-cyclic index layout over a two dimensional process grid.
+pub struct BlockCyclicLayout<'a, C: Communicator> {
+    shape: (usize, usize),
+    block_shape: (usize, usize),
+    grid_shape: (usize, usize),
+    comm: CommRef<'a, C>,
+}
+
+impl<C: Communicator> std::fmt::Debug for BlockCyclicLayout<'_, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "BlockCyclicLayout of a {}x{} matrix with {}x{} blocks on a {}x{} process grid.",
+            self.shape.0,
+            self.shape.1,
+            self.block_shape.0,
+            self.block_shape.1,
+            self.grid_shape.0,
+            self.grid_shape.1
+        )
+    }
+}
+
+impl<C: Communicator> Clone for BlockCyclicLayout<'_, C> {
+    fn clone(&self) -> Self {
+        Self {
+            shape: self.shape,
+            block_shape: self.block_shape,
+            grid_shape: self.grid_shape,
+            comm: self.comm.clone(),
+        }
+    }
+}
+
+impl<'a, C: Communicator> BlockCyclicLayout<'a, C> {
+    /// Create a one dimensional block-cyclic layout.
+    ///
+    /// The `nindices` indices are split into blocks of `block_size` indices. Block `k` is
+    /// owned by the process with rank `k % comm.size()`.
+    pub fn new(nindices: usize, block_size: usize, comm: impl Into<CommRef<'a, C>>) -> Self {
+        let comm = comm.into();
+        Self::new_2d(
+            (nindices, 1),
+            (block_size, 1),
+            (comm.size() as usize, 1),
+            comm,
+        )
+    }
+
+    /// Create a two dimensional block-cyclic layout.
+    ///
+    /// # Arguments
+    /// - `shape` - The number of rows and columns of the matrix.
+    /// - `block_shape` - The number of rows and columns of each block.
+    /// - `grid_shape` - The number of rows and columns of the process grid. The product must
+    ///   be the size of the communicator.
+    /// - `comm` - The MPI communicator, borrowed or shared, see [CommRef].
+    pub fn new_2d(
+        shape: (usize, usize),
+        block_shape: (usize, usize),
+        grid_shape: (usize, usize),
+        comm: impl Into<CommRef<'a, C>>,
+    ) -> Self {
+        let comm = comm.into();
+        assert!(
+            block_shape.0 > 0 && block_shape.1 > 0,
+            "Block sizes must be positive."
+        );
+        assert_eq!(
+            grid_shape.0 * grid_shape.1,
+            comm.size() as usize,
+            "The process grid must contain all processes of the communicator."
+        );
+
+        Self {
+            shape,
+            block_shape,
+            grid_shape,
+            comm,
+        }
+    }
+
+    /// The number of rows and columns of the matrix.
+    pub fn shape(&self) -> (usize, usize) {
+        self.shape
+    }
+
+    /// The number of rows and columns of each block.
+    pub fn block_shape(&self) -> (usize, usize) {
+        self.block_shape
+    }
+
+    /// The number of rows and columns of the process grid.
+    pub fn grid_shape(&self) -> (usize, usize) {
+        self.grid_shape
+    }
+
+    /// The position of a process in the process grid.
+    pub fn grid_position(&self, rank: usize) -> (usize, usize) {
+        (rank / self.grid_shape.1, rank % self.grid_shape.1)
+    }
+
+    /// The number of local rows and columns on a given rank.
+    pub fn local_shape(&self, rank: usize) -> (usize, usize) {
+        let (row, column) = self.grid_position(rank);
+        (
+            number_of_local_entries(self.shape.0, self.block_shape.0, row, self.grid_shape.0),
+            number_of_local_entries(self.shape.1, self.block_shape.1, column, self.grid_shape.1),
+        )
+    }
+
+    /// The number of global indices.
+    pub fn number_of_global_indices(&self) -> usize {
+        self.shape.0 * self.shape.1
+    }
+
+    /// The number of local indices on the current process.
+    pub fn number_of_local_indices(&self) -> usize {
+        let (rows, columns) = self.local_shape(self.comm.rank() as usize);
+        rows * columns
+    }
+
+    /// Get the rank of a given index.
+    ///
+    /// Returns `None` if the index is out of bounds.
+    pub fn rank_from_index(&self, index: usize) -> Option<usize> {
+        self.location(index).map(|(rank, _)| rank)
+    }
+
+    /// Get the ranks of several indices.
+    ///
+    /// Panics if an index is out of bounds. See [BlockCyclicLayout::try_ranks_from_indices]
+    /// for a non-panicking version.
+    pub fn ranks_from_indices(&self, indices: &[usize]) -> Vec<usize> {
+        self.try_ranks_from_indices(indices)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Get the ranks of several indices.
+    ///
+    /// Returns [Error::IndexOutOfRange] for the first index that is out of bounds.
+    pub fn try_ranks_from_indices(&self, indices: &[usize]) -> Result<Vec<usize>, Error> {
+        indices
+            .iter()
+            .map(|&index| {
+                self.rank_from_index(index).ok_or(Error::IndexOutOfRange {
+                    index,
+                    bound: self.number_of_global_indices(),
+                })
+            })
+            .collect()
+    }
+
+    /// Convert global index to local index on a given rank.
+    ///
+    /// Returns `None` if index does not exist on rank.
+    pub fn global2local(&self, rank: usize, index: usize) -> Option<usize> {
+        self.location(index)
+            .filter(|&(owner, _)| owner == rank)
+            .map(|(_, local_index)| local_index)
+    }
+
+    /// Convert a local index on the current process to a global index.
+    ///
+    /// Returns `None` if `index` is out of bounds.
+    pub fn local2global(&self, index: usize) -> Option<usize> {
+        let rank = self.comm.rank() as usize;
+        let (row, column) = self.grid_position(rank);
+        let (local_rows, local_columns) = self.local_shape(rank);
+
+        if index >= local_rows * local_columns {
+            return None;
+        }
+
+        let i = local2global_1d(
+            index % local_rows,
+            self.block_shape.0,
+            row,
+            self.grid_shape.0,
+        );
+        let j = local2global_1d(
+            index / local_rows,
+            self.block_shape.1,
+            column,
+            self.grid_shape.1,
+        );
+
+        Some(i + j * self.shape.0)
+    }
+
+    /// Move data from a contiguous index layout to this layout.
+    ///
+    /// `data` holds the values of the local indices of `source`. Returns the values of the
+    /// local indices of this layout. This is a collective operation.
+    pub fn remap_from_index_layout<T: Equivalence + Copy>(
+        &self,
+        source: &IndexLayout<'a, C>,
+        data: &[T],
+    ) -> Vec<T> {
+        remap_data(source, self, data)
+    }
+
+    /// Move data from this layout to a contiguous index layout.
+    ///
+    /// `data` holds the values of the local indices of this layout. Returns the values of the
+    /// local indices of `target`. This is a collective operation.
+    pub fn remap_to_index_layout<T: Equivalence + Copy>(
+        &self,
+        target: &IndexLayout<'a, C>,
+        data: &[T],
+    ) -> Vec<T> {
+        remap_data(self, target, data)
+    }
+
+    /// Return the communicator.
+    pub fn comm(&self) -> &C {
+        &self.comm
+    }
+
+    /// Return the borrowed or shared communicator.
+    pub fn comm_ref(&self) -> &CommRef<'a, C> {
+        &self.comm
+    }
+
+    /// Return the owning rank and the local index of a global index.
+    fn location(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.number_of_global_indices() {
+            return None;
+        }
+
+        let (row, local_row) =
+            global2local_1d(index % self.shape.0, self.block_shape.0, self.grid_shape.0);
+        let (column, local_column) =
+            global2local_1d(index / self.shape.0, self.block_shape.1, self.grid_shape.1);
+
+        let rank = row * self.grid_shape.1 + column;
+        let local_rows = self.local_shape(rank).0;
+
+        Some((rank, local_row + local_column * local_rows))
+    }
+}
+
+impl<'a, C: Communicator> Layout<'a> for BlockCyclicLayout<'a, C> {
+    type Comm = C;
+
+    fn comm_ref(&self) -> &CommRef<'a, C> {
+        &self.comm
+    }
+
+    fn comm(&self) -> &C {
+        &self.comm
+    }
+
+    fn number_of_global_indices(&self) -> usize {
+        BlockCyclicLayout::number_of_global_indices(self)
+    }
+
+    fn number_of_local_indices(&self) -> usize {
+        BlockCyclicLayout::number_of_local_indices(self)
+    }
+
+    fn local_range(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    fn local2global(&self, index: usize) -> Option<usize> {
+        BlockCyclicLayout::local2global(self, index)
+    }
+
+    fn global2local_owned(&self, index: usize) -> Option<usize> {
+        self.global2local(self.comm.rank() as usize, index)
+    }
+
+    fn ranks_from_indices(&self, indices: &[usize]) -> Vec<usize> {
+        BlockCyclicLayout::ranks_from_indices(self, indices)
+    }
+
+    fn try_ranks_from_indices(&self, indices: &[usize]) -> Result<Vec<usize>, Error> {
+        BlockCyclicLayout::try_ranks_from_indices(self, indices)
+    }
+}
+
+/// The number of entries of one dimension that are stored on a process.
+///
+/// This is the `NUMROC` function of ScaLAPACK for a distribution starting on process `0`.
+fn number_of_local_entries(
+    n: usize,
+    block_size: usize,
+    process: usize,
+    nprocesses: usize,
+) -> usize {
+    let nblocks = n / block_size;
+    let mut count = (nblocks / nprocesses) * block_size;
+    let extra_blocks = nblocks % nprocesses;
+
+    if process < extra_blocks {
+        count += block_size;
+    } else if process == extra_blocks {
+        count += n % block_size;
+    }
+
+    count
+}
+
+/// Return the owning process and the local index of a global index in one dimension.
+fn global2local_1d(index: usize, block_size: usize, nprocesses: usize) -> (usize, usize) {
+    let block = index / block_size;
+    (
+        block % nprocesses,
+        (block / nprocesses) * block_size + index % block_size,
+    )
+}
+
+/// Return the global index of a local index on a process in one dimension.
+fn local2global_1d(index: usize, block_size: usize, process: usize, nprocesses: usize) -> usize {
+    let local_block = index / block_size;
+    (local_block * nprocesses + process) * block_size + index % block_size
+}
+
+#[cfg(test)]
+mod test {
+    use super::{global2local_1d, local2global_1d, number_of_local_entries};
+
+    #[test]
+    fn test_block_cyclic_index_maps() {
+        let (n, block_size, nprocesses) = (20, 3, 3);
+
+        let total = (0..nprocesses)
+            .map(|process| number_of_local_entries(n, block_size, process, nprocesses))
+            .sum::<usize>();
+        assert_eq!(total, n);
+
+        for index in 0..n {
+            let (process, local_index) = global2local_1d(index, block_size, nprocesses);
+            assert!(local_index < number_of_local_entries(n, block_size, process, nprocesses));
+            assert_eq!(
+                local2global_1d(local_index, block_size, process, nprocesses),
+                index
+            );
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Collective checks of the index inputs of constructors.
+//!
+//! The checks are only compiled with the `checked` feature. Each check first looks for an
+//! invalid input on the current process and then agrees with all other processes on whether
+//! any input was invalid. If so, all processes panic. The process with the invalid input reports
+//! the offending index, the others report the ranks of the processes with invalid inputs. This
+//! avoids deadlocks from processes that continue into the next collective operation.
+
+use mpi::traits::Communicator;
+
+use crate::error::failed_ranks;
+use crate::layout::Layout;
+
+/// Panic on all processes if any process reports an error.
+pub(crate) fn check_collectively<C: Communicator>(comm: &C, error: Option<String>) {
+    let failed_ranks = failed_ranks(comm, error.is_some());
+
+    if let Some(message) = error {
+        panic!("Invalid input on rank {}: {}", comm.rank(), message);
+    }
+
+    if !failed_ranks.is_empty() {
+        panic!("Invalid input on ranks {:?}.", failed_ranks);
+    }
+}
+
+/// Check that the owning ranks of ghost indices are valid ranks other than the current rank.
+pub(crate) fn check_owning_ranks<C: Communicator>(
+    number_of_ghost_indices: usize,
+    owning_ranks: &[usize],
+    comm: &C,
+) {
+    let rank = comm.rank() as usize;
+    let size = comm.size() as usize;
+
+    let error = if number_of_ghost_indices != owning_ranks.len() {
+        Some(format!(
+            "{} ghost indices but {} owning ranks.",
+            number_of_ghost_indices,
+            owning_ranks.len()
+        ))
+    } else {
+        owning_ranks
+            .iter()
+            .enumerate()
+            .find(|(_, &owner)| owner >= size || owner == rank)
+            .map(|(position, &owner)| {
+                format!(
+                    "Ghost index at position {} has the invalid owning rank {}.",
+                    position, owner
+                )
+            })
+    };
+
+    check_collectively(comm, error);
+}
+
+/// Check that all indices are global indices of a layout.
+pub(crate) fn check_global_indices<'a, L: Layout<'a>>(index_layout: &L, indices: &[usize]) {
+    let number_of_global_indices = index_layout.number_of_global_indices();
+
+    let error = indices
+        .iter()
+        .enumerate()
+        .find(|(_, &index)| index >= number_of_global_indices)
+        .map(|(position, &index)| {
+            format!(
+                "Index {} at position {} out of bounds for {} global indices.",
+                index, position, number_of_global_indices
+            )
+        });
+
+    check_collectively(index_layout.comm(), error);
+}
+
+/// Check that every owned index is requested exactly once.
+///
+/// `requested_local_indices` are the local indices of the owned indices that are requested by
+/// any process, including the current one.
+pub(crate) fn check_requested_once<'a, L: Layout<'a>>(
+    index_layout: &L,
+    requested_local_indices: impl Iterator<Item = usize>,
+) {
+    let mut requests = vec![0; index_layout.number_of_local_indices()];
+    for local_index in requested_local_indices {
+        requests[local_index] += 1;
+    }
+
+    let error = requests
+        .iter()
+        .position(|&count| count != 1)
+        .map(|local_index| {
+            format!(
+                "Index {} is requested {} times.",
+                index_layout.local2global(local_index).unwrap(),
+                requests[local_index]
+            )
+        });
+
+    check_collectively(index_layout.comm(), error);
+}
@@ -0,0 +1,73 @@
+//! Borrowed or shared communicators.
+//!
+//! Index layouts and ghost communicators hold their communicator in a [CommRef]. A borrowed
+//! communicator ties the lifetime `'a` of these types to the communicator, e.g. to the world
+//! communicator in `main`. A shared communicator is reference counted, so that the types can
+//! be used with `'a = 'static`, stored in long-lived structs and returned from functions.
+//!
+//! Constructors accept `&C` for a borrowed communicator and `Rc<C>` for a shared one. MPI
+//! communicators cannot be sent to other threads, so the reference count is not atomic.
+//! [CommRef::duplicate] creates a shared duplicate of a communicator.
+
+use std::ops::Deref;
+use std::rc::Rc;
+
+use mpi::topology::SimpleCommunicator;
+use mpi::traits::Communicator;
+
+/// A borrowed or shared communicator.
+pub enum CommRef<'a, C: Communicator> {
+    /// A borrowed communicator.
+    Borrowed(&'a C),
+    /// A reference counted communicator.
+    Shared(Rc<C>),
+}
+
+impl<C: Communicator> CommRef<'_, C> {
+    /// Return true if the communicator is reference counted.
+    pub fn is_shared(&self) -> bool {
+        matches!(self, CommRef::Shared(_))
+    }
+}
+
+impl CommRef<'static, SimpleCommunicator> {
+    /// Duplicate a communicator (`MPI_Comm_dup`) and share the duplicate.
+    ///
+    /// The duplicate is freed when the last type holding it is dropped. This is a collective
+    /// operation.
+    pub fn duplicate<D: Communicator>(comm: &D) -> Self {
+        CommRef::Shared(Rc::new(comm.duplicate()))
+    }
+}
+
+impl<C: Communicator> Clone for CommRef<'_, C> {
+    fn clone(&self) -> Self {
+        match self {
+            CommRef::Borrowed(comm) => CommRef::Borrowed(comm),
+            CommRef::Shared(comm) => CommRef::Shared(comm.clone()),
+        }
+    }
+}
+
+impl<C: Communicator> Deref for CommRef<'_, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        match self {
+            CommRef::Borrowed(comm) => comm,
+            CommRef::Shared(comm) => comm,
+        }
+    }
+}
+
+impl<'a, C: Communicator> From<&'a C> for CommRef<'a, C> {
+    fn from(comm: &'a C) -> Self {
+        CommRef::Borrowed(comm)
+    }
+}
+
+impl<C: Communicator> From<Rc<C>> for CommRef<'_, C> {
+    fn from(comm: Rc<C>) -> Self {
+        CommRef::Shared(comm)
+    }
+}
@@ -5,169 +5,498 @@
 //! Hence, some dofs are needed on both processes. The `Global2LocalDataMapper` establishes the corresponding
 //! communication and maps distributed vectors of global dofs to the required dofs on each process.
 
-use std::{collections::HashMap, rc::Rc};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use itertools::{izip, Itertools};
+use itertools::izip;
 use mpi::traits::{Communicator, Equivalence};
 
+use crate::error::{agree_collectively, check_size, Error};
+use crate::field_batch::FieldBatch;
+use crate::ghost_communicator::ChunkedCounts;
+use crate::indexed_exchange::{IndexedDatatypes, IndexedExchange};
+use crate::layout::Layout;
+use crate::reduction::{combine_slices, ReductionOp};
 use crate::IndexLayout;
 
 /// Maps global data to local data.
-pub struct Global2LocalDataMapper<'a, C: Communicator> {
-    index_layout: Rc<IndexLayout<'a, C>>,
-    ghost_communicator: crate::GhostCommunicator<usize>,
-    ghost_to_position: HashMap<usize, usize>,
+///
+/// The index layout can be any [Layout] and defaults to an [IndexLayout].
+pub struct Global2LocalDataMapper<'a, C: Communicator, L: Layout<'a, Comm = C> = IndexLayout<'a, C>>
+{
+    index_layout: Rc<L>,
+    ghost_communicator: crate::GhostCommunicator<'a, usize, C>,
     required_dofs: Vec<usize>,
+    // Local positions of the send indices of the ghost communicator.
+    send_positions: Vec<usize>,
+    // Source of the data of each required dof.
+    gather_plan: Vec<GatherSource>,
+    indexed_exchange: Option<IndexedExchange>,
+    ghost_positions: Vec<usize>,
+    // Identifies the plans that were created for the current exchange setup of this mapper.
+    id: usize,
 }
 
-impl<'a, C: Communicator> Global2LocalDataMapper<'a, C> {
-    /// Create a new data mapper.
-    ///
-    /// The `required_dofs` are the dofs that are required on the local process.
-    pub fn new(index_layout: Rc<IndexLayout<'a, C>>, required_dofs: &[usize]) -> Self {
-        let comm = index_layout.comm();
-        let rank = comm.rank() as usize;
-
-        // First we go through the required dofs and get the ghosts
-
-        let mut ghost_dofs = Vec::<usize>::new();
+/// Return a new identifier for the plans of a data mapper.
+fn next_plan_id() -> usize {
+    static NEXT_PLAN_ID: AtomicUsize = AtomicUsize::new(0);
+    NEXT_PLAN_ID.fetch_add(1, Ordering::Relaxed)
+}
 
-        for &dof in required_dofs.iter() {
-            if index_layout.rank_from_index(dof).unwrap() != rank {
-                ghost_dofs.push(dof);
-            }
-        }
+/// Precomputed data for repeated mappings of values of type `T` with a fixed chunk size.
+///
+/// A plan is created with [Global2LocalDataMapper::plan] and holds the counts of the exchange
+/// scaled by the chunk size, the exchange buffers and, if derived datatypes are used, the
+/// datatypes of the exchange. A plan can only be used with the mapper that created it.
+pub struct DataMapPlan<T> {
+    mapper_id: usize,
+    counts: ChunkedCounts,
+    send_buffer: Vec<T>,
+    receive_buffer: Vec<T>,
+    datatypes: Option<IndexedDatatypes>,
+}
 
-        // Now make sure that the ghosts are unique
+impl<T> DataMapPlan<T> {
+    /// The number of values per dof.
+    pub fn chunk_size(&self) -> usize {
+        self.counts.chunk_size()
+    }
+}
 
-        let ghost_dofs = ghost_dofs.iter().unique().copied().collect_vec();
+/// The source of the data of a required dof.
+#[derive(Clone, Copy, Debug)]
+enum GatherSource {
+    /// The dof is owned and has the given local index.
+    Owned(usize),
+    /// The dof is a ghost with the given receive position.
+    Ghost(usize),
+}
 
-        // Get the ranks of the ghost
+impl<'a, C: Communicator, L: Layout<'a, Comm = C>> Global2LocalDataMapper<'a, C, L> {
+    /// Create a new data mapper.
+    ///
+    /// The `required_dofs` are the dofs that are required on the local process.
+    pub fn new(index_layout: Rc<L>, required_dofs: &[usize]) -> Self {
+        #[cfg(feature = "checked")]
+        crate::check::check_global_indices(index_layout.as_ref(), required_dofs);
 
-        let ghost_owners = ghost_dofs
-            .iter()
-            .map(|&dof| index_layout.rank_from_index(dof).unwrap())
-            .collect_vec();
+        // We setup the ghost communicator for all required dofs that are not owned
+        // by the current process.
 
-        // We now setup the ghost communicator
+        let ghost_communicator =
+            crate::GhostCommunicator::from_layout(index_layout.as_ref(), required_dofs, true);
 
-        let ghost_communicator = crate::GhostCommunicator::new(&ghost_dofs, &ghost_owners, comm);
+        // Precompute where the owned data that is sent to other processes is stored and
+        // where the data of each required dof comes from.
 
-        // We now create a dof to position array for the ghosts
+        let send_positions = ghost_communicator
+            .send_indices()
+            .iter()
+            .map(|&dof| index_layout.global2local_owned(dof).unwrap())
+            .collect();
 
-        let ghost_to_position = HashMap::<usize, usize>::from_iter(
-            ghost_communicator
-                .receive_indices
-                .iter()
-                .enumerate()
-                .map(|(i, &d)| (d, i)),
-        );
+        let gather_plan = required_dofs
+            .iter()
+            .map(|&dof| match index_layout.global2local_owned(dof) {
+                Some(local_dof) => GatherSource::Owned(local_dof),
+                None => GatherSource::Ghost(ghost_communicator.receive_position(dof).unwrap()),
+            })
+            .collect();
 
         Self {
             index_layout,
             ghost_communicator,
-            ghost_to_position,
             required_dofs: required_dofs.to_vec(),
+            send_positions,
+            gather_plan,
+            indexed_exchange: None,
+            ghost_positions: Vec::new(),
+            id: next_plan_id(),
+        }
+    }
+
+    /// Use MPI derived datatypes for the data exchange.
+    ///
+    /// If enabled, [Global2LocalDataMapper::map_data] sends values directly from the input
+    /// data and receives the ghosts directly into the output data instead of going through
+    /// intermediate buffers. The datatypes are created on first use for each value type
+    /// and chunk size. Plans created before this call can not be used any more.
+    pub fn use_derived_datatypes(&mut self, enable: bool) {
+        self.id = next_plan_id();
+        if !enable {
+            self.indexed_exchange = None;
+            self.ghost_positions = Vec::new();
+            return;
+        }
+
+        // A ghost may be required several times. It is received into the first position
+        // at which it is required and copied from there to the other positions.
+
+        let mut ghost_positions = vec![usize::MAX; self.ghost_communicator.total_receive_count()];
+        for (position, source) in self.gather_plan.iter().enumerate().rev() {
+            if let GatherSource::Ghost(receive_position) = *source {
+                ghost_positions[receive_position] = position;
+            }
         }
+
+        self.indexed_exchange = Some(IndexedExchange::new(
+            &self.ghost_communicator,
+            &self.send_positions,
+            &ghost_positions,
+        ));
+        self.ghost_positions = ghost_positions;
     }
 
     /// Map global data to the local required data
     ///
     /// The input data is a vector of global data. A chunk size can be given in case multiple elements
     /// are associated with each dof.
+    ///
+    /// Panics if the arguments are invalid. See [Global2LocalDataMapper::try_map_data] for a
+    /// non-panicking version.
     pub fn map_data<T: Equivalence + Copy + std::fmt::Debug>(
         &self,
         data: &[T],
         chunk_size: usize,
     ) -> Vec<T> {
-        // First we need to go through the send dofs and set up the data that needs to be sent.
-
-        let rank = self.index_layout.comm().rank() as usize;
-
-        // Prepare the send data
-
-        let send_data = {
-            let mut tmp =
-                Vec::<T>::with_capacity(self.ghost_communicator.total_send_count() * chunk_size);
-            let send_buffer: &mut [T] = unsafe { std::mem::transmute(tmp.spare_capacity_mut()) };
-            for (global_send_index, send_buffer_chunk) in izip!(
-                self.ghost_communicator.send_indices().iter(),
-                send_buffer.chunks_mut(chunk_size)
-            ) {
-                let local_start_index = self
-                    .index_layout
-                    .global2local(rank, *global_send_index)
-                    .unwrap()
-                    * chunk_size;
-                let local_end_index = local_start_index + chunk_size;
-                send_buffer_chunk.copy_from_slice(&data[local_start_index..local_end_index]);
-            }
-            unsafe { tmp.set_len(self.ghost_communicator.total_send_count() * chunk_size) };
-            tmp
-        };
+        self.try_map_data(data, chunk_size)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Map global data to the local required data.
+    ///
+    /// Returns [Error::SizeMismatch] if `data` does not have `chunk_size` values for each
+    /// local index. The processes agree on the result of the checks before any data is
+    /// exchanged, so that all processes return [Error::FailedOnOtherRanks] if the arguments
+    /// are invalid on another process. Returns [Error::Mpi] if MPI reports an error during the
+    /// exchange.
+    pub fn try_map_data<T: Equivalence + Copy + std::fmt::Debug>(
+        &self,
+        data: &[T],
+        chunk_size: usize,
+    ) -> Result<Vec<T>, Error> {
+        agree_collectively(
+            self.index_layout.comm(),
+            check_size(
+                chunk_size * self.index_layout.number_of_local_indices(),
+                data.len(),
+            ),
+        )?;
+
+        let nelems = chunk_size * self.required_dofs.len();
+        let mut output_data = Vec::<T>::with_capacity(nelems);
+        let output_buffer: &mut [T] =
+            unsafe { std::mem::transmute(output_data.spare_capacity_mut()) };
+
+        self.exchange_into(data, &mut output_buffer[..nelems], chunk_size)?;
+
+        unsafe { output_data.set_len(nelems) };
+        Ok(output_data)
+    }
 
-        // Now get the receive data
-
-        let receive_data = {
-            let mut tmp =
-                Vec::<T>::with_capacity(self.ghost_communicator.total_receive_count() * chunk_size);
-            let receive_buffer: &mut [T] = unsafe { std::mem::transmute(tmp.spare_capacity_mut()) };
-            self.ghost_communicator.forward_send_values_by_chunks(
-                &send_data,
-                receive_buffer,
-                chunk_size,
-            );
-            unsafe { tmp.set_len(self.ghost_communicator.total_receive_count() * chunk_size) };
-            tmp
+    /// Create a plan for repeated mappings of values of type `T` with `chunk_size` values per dof.
+    ///
+    /// The plan reflects whether derived datatypes are used, see
+    /// [Global2LocalDataMapper::use_derived_datatypes].
+    pub fn plan<T: Equivalence>(&self, chunk_size: usize) -> DataMapPlan<T> {
+        let (send_buffer, receive_buffer) = if self.indexed_exchange.is_some() {
+            (Vec::new(), Vec::new())
+        } else {
+            (
+                Vec::with_capacity(chunk_size * self.ghost_communicator.total_send_count()),
+                Vec::with_capacity(chunk_size * self.ghost_communicator.total_receive_count()),
+            )
         };
 
-        // We have the receive data from the other processes. We now need to setup the output vector
-        // and collect the data from what is already on the process and from the ghosts.
+        DataMapPlan {
+            mapper_id: self.id,
+            counts: self.ghost_communicator.chunked_counts(chunk_size),
+            send_buffer,
+            receive_buffer,
+            datatypes: self
+                .indexed_exchange
+                .as_ref()
+                .map(|indexed_exchange| indexed_exchange.create_datatypes::<T>(chunk_size)),
+        }
+    }
+
+    /// Map global data to the local required data in a given output buffer.
+    ///
+    /// `data` must have `chunk_size` values for each local index and `out_data` must have
+    /// `chunk_size` values for each required dof. The positions of the owned dofs and ghosts of
+    /// all required dofs are precomputed when the mapper is created. With derived datatypes the
+    /// ghosts are received directly into `out_data` with the cached datatypes, otherwise
+    /// exchange buffers are allocated for each call. Use
+    /// [Global2LocalDataMapper::map_data_into_with_plan] to reuse the buffers as well.
+    pub fn map_data_into<T: Equivalence + Copy>(
+        &self,
+        data: &[T],
+        out_data: &mut [T],
+        chunk_size: usize,
+    ) {
+        self.assert_sizes(data, out_data, chunk_size);
+        self.exchange_into(data, out_data, chunk_size)
+            .unwrap_or_else(|error| panic!("{}", error));
+    }
+
+    /// Map global data to the local required data with a precomputed plan.
+    ///
+    /// `out_data` must have `plan.chunk_size()` entries for each required dof. The scaled counts,
+    /// the exchange buffers and the datatypes are kept in the `plan`, so that repeated calls with
+    /// the same plan do not allocate or look up dofs. Panics if the plan was not created by this
+    /// mapper or was created before the last call to
+    /// [Global2LocalDataMapper::use_derived_datatypes].
+    pub fn map_data_into_with_plan<T: Equivalence + Copy>(
+        &self,
+        plan: &mut DataMapPlan<T>,
+        data: &[T],
+        out_data: &mut [T],
+    ) {
+        assert_eq!(
+            plan.mapper_id, self.id,
+            "The plan was not created by this data mapper or is outdated."
+        );
+        self.assert_sizes(data, out_data, plan.chunk_size());
+        self.map_data_with_plan(plan, data, out_data)
+            .unwrap_or_else(|error| panic!("{}", error));
+    }
+
+    /// Check the sizes of the input and output data of a mapping.
+    fn assert_sizes<T>(&self, data: &[T], out_data: &[T], chunk_size: usize) {
+        assert_eq!(
+            data.len(),
+            chunk_size * self.index_layout.number_of_local_indices()
+        );
+        assert_eq!(out_data.len(), chunk_size * self.required_dofs.len());
+    }
+
+    /// Map global data into an output buffer of checked size.
+    fn exchange_into<T: Equivalence + Copy>(
+        &self,
+        data: &[T],
+        out_data: &mut [T],
+        chunk_size: usize,
+    ) -> Result<(), Error> {
+        match &self.indexed_exchange {
+            Some(indexed_exchange) => {
+                indexed_exchange.try_forward_send_values_by_chunks(
+                    &self.ghost_communicator,
+                    data,
+                    out_data,
+                    chunk_size,
+                )?;
+                self.gather_with_datatypes(data, out_data, chunk_size);
+                Ok(())
+            }
+            None => self.map_data_with_plan(&mut self.plan(chunk_size), data, out_data),
+        }
+    }
+
+    /// Map global data with a plan of this mapper and checked sizes.
+    fn map_data_with_plan<T: Equivalence + Copy>(
+        &self,
+        plan: &mut DataMapPlan<T>,
+        data: &[T],
+        out_data: &mut [T],
+    ) -> Result<(), Error> {
+        let chunk_size = plan.chunk_size();
+
+        if let (Some(indexed_exchange), Some(datatypes)) =
+            (&self.indexed_exchange, &mut plan.datatypes)
+        {
+            // The ghosts are received directly into the output data.
+
+            indexed_exchange.forward_send_values_with_datatypes(
+                &self.ghost_communicator,
+                data,
+                out_data,
+                datatypes,
+            )?;
+            self.gather_with_datatypes(data, out_data, chunk_size);
+            return Ok(());
+        }
+
+        let DataMapPlan {
+            counts,
+            send_buffer,
+            receive_buffer,
+            ..
+        } = plan;
+
+        // Collect the owned data that other processes require. The buffers have the required
+        // capacity from the creation of the plan.
+
+        send_buffer.clear();
+        for &position in &self.send_positions {
+            send_buffer
+                .extend_from_slice(&data[position * chunk_size..(1 + position) * chunk_size]);
+        }
+
+        // Receive the ghosts.
+
+        let receive_count = chunk_size * self.ghost_communicator.total_receive_count();
+        receive_buffer.clear();
+        receive_buffer.reserve(receive_count);
+        let receive_slice: &mut [T] =
+            unsafe { std::mem::transmute(receive_buffer.spare_capacity_mut()) };
+        self.ghost_communicator.forward_send_values_with_counts(
+            send_buffer,
+            &mut receive_slice[..receive_count],
+            counts,
+        )?;
+        unsafe { receive_buffer.set_len(receive_count) };
+
+        // Collect the output from the owned data and the ghosts.
+
+        for (source, output_chunk) in izip!(&self.gather_plan, out_data.chunks_mut(chunk_size)) {
+            let (values, position) = match *source {
+                GatherSource::Owned(local_dof) => (data, local_dof),
+                GatherSource::Ghost(receive_position) => (&receive_buffer[..], receive_position),
+            };
+            output_chunk
+                .copy_from_slice(&values[position * chunk_size..(1 + position) * chunk_size]);
+        }
+
+        Ok(())
+    }
+
+    /// Map several fields at once.
+    ///
+    /// Each field of the batch maps its input, the data of the owned dofs, to its output, the
+    /// data of the required dofs, as in [Global2LocalDataMapper::map_data_into]. The values of
+    /// all fields are exchanged together with a single message per neighbour. The batch is
+    /// consumed, which releases the borrowed outputs.
+    pub fn map_data_batch(&self, mut batch: FieldBatch<'_>) {
+        batch.assert_counts(
+            self.index_layout.number_of_local_indices(),
+            self.required_dofs.len(),
+        );
+        if batch.is_empty() {
+            return;
+        }
 
-        let output_data = {
-            let total_number_of_dofs = self.required_dofs.len();
-            let mut output_data = Vec::<T>::with_capacity(total_number_of_dofs * chunk_size);
+        let layout = batch.record_layout(self.ghost_communicator.comm());
+        let record_size = layout.record_size();
 
-            let output_buffer: &mut [T] =
-                unsafe { std::mem::transmute(output_data.spare_capacity_mut()) };
+        let send_records = batch.pack(&layout, self.send_positions.iter().copied());
+        let mut receive_records = layout.records(self.ghost_communicator.total_receive_count());
+        self.ghost_communicator.forward_send_values_by_chunks(
+            &send_records,
+            &mut receive_records,
+            record_size,
+        );
 
-            // We go through the dofs one by one, check whether it is owned or a ghost
-            // and copy over the corresponding data
+        for (position, source) in self.gather_plan.iter().enumerate() {
+            match *source {
+                GatherSource::Owned(local_dof) => batch.copy(local_dof, position),
+                GatherSource::Ghost(receive_position) => batch.unpack_record(
+                    &layout,
+                    &receive_records
+                        [receive_position * record_size..(1 + receive_position) * record_size],
+                    position,
+                ),
+            }
+        }
+    }
+
+    /// Complete a mapping after the ghosts were received into `out_data` with derived datatypes.
+    fn gather_with_datatypes<T: Copy>(&self, data: &[T], out_data: &mut [T], chunk_size: usize) {
+        // Now copy the owned dofs and the ghosts that are required more than once.
 
-            for (&dof, output_chunk) in izip!(
-                self.required_dofs.iter(),
-                output_buffer.chunks_mut(chunk_size)
-            ) {
-                if self.index_layout.rank_from_index(dof).unwrap() == rank {
-                    // Dof is owned. Need to copy the corresponding data
-                    let local_dof = self.index_layout.global2local(rank, dof).unwrap();
+        for (position, source) in self.gather_plan.iter().enumerate() {
+            let output_start = position * chunk_size;
+            match *source {
+                GatherSource::Owned(local_dof) => {
                     let local_data_start = local_dof * chunk_size;
-                    let local_data_end = local_data_start + chunk_size;
+                    out_data[output_start..output_start + chunk_size]
+                        .copy_from_slice(&data[local_data_start..local_data_start + chunk_size]);
+                }
+                GatherSource::Ghost(receive_position) => {
+                    let ghost_start = self.ghost_positions[receive_position] * chunk_size;
+                    if ghost_start != output_start {
+                        out_data.copy_within(ghost_start..ghost_start + chunk_size, output_start);
+                    }
+                }
+            }
+        }
+    }
 
-                    output_chunk.copy_from_slice(&data[local_data_start..local_data_end]);
+    /// Reduce local values of the required dofs onto the owners of the dofs.
+    ///
+    /// This is the reverse of [Global2LocalDataMapper::map_data]. `local_values` holds
+    /// `chunk_size` values for each of the required dofs. All values for the same dof, from
+    /// duplicates in the required dofs of a process and from all processes that require the
+    /// dof, are combined with `op`. Returns the reduced values of the dofs owned by the current
+    /// process. Owned dofs that are not required by any process are set to the default value.
+    /// This is a collective operation.
+    pub fn reverse_map_data<T, Op>(&self, local_values: &[T], chunk_size: usize, op: Op) -> Vec<T>
+    where
+        T: Equivalence + Copy + Default,
+        Op: ReductionOp<T>,
+    {
+        assert_eq!(local_values.len(), chunk_size * self.required_dofs.len());
+
+        // The first value for a dof is copied, all further values are combined with it.
+
+        let accumulate =
+            |accumulators: &mut [T], touched: &mut [bool], position: usize, chunk: &[T]| {
+                let target = &mut accumulators[position * chunk_size..(1 + position) * chunk_size];
+                if touched[position] {
+                    combine_slices(&op, target, chunk);
                 } else {
-                    // Dof is a ghost Need to copy from ghosts
-                    let receive_start = self.ghost_to_position[&dof] * chunk_size;
-                    let receive_end = receive_start + chunk_size;
+                    target.copy_from_slice(chunk);
+                    touched[position] = true;
+                }
+            };
+
+        let number_of_local_indices = self.index_layout.number_of_local_indices();
+        let mut owned_values = vec![T::default(); chunk_size * number_of_local_indices];
+        let mut owned_touched = vec![false; number_of_local_indices];
+
+        let total_receive_count = self.ghost_communicator.total_receive_count();
+        let mut ghost_values = vec![T::default(); chunk_size * total_receive_count];
+        let mut ghost_touched = vec![false; total_receive_count];
+
+        // Combine the local values for each owned dof and for each ghost.
 
-                    output_chunk.copy_from_slice(&receive_data[receive_start..receive_end]);
+        for (source, chunk) in izip!(&self.gather_plan, local_values.chunks(chunk_size)) {
+            match *source {
+                GatherSource::Owned(local_dof) => {
+                    accumulate(&mut owned_values, &mut owned_touched, local_dof, chunk)
                 }
+                GatherSource::Ghost(receive_position) => accumulate(
+                    &mut ghost_values,
+                    &mut ghost_touched,
+                    receive_position,
+                    chunk,
+                ),
             }
+        }
 
-            unsafe { output_data.set_len(total_number_of_dofs * chunk_size) };
-            output_data
-        };
+        // Send the combined ghost values back to the owners.
+
+        let mut contributions =
+            vec![T::default(); chunk_size * self.ghost_communicator.total_send_count()];
+        self.ghost_communicator.backward_send_values_by_chunks(
+            &ghost_values,
+            &mut contributions,
+            chunk_size,
+        );
+
+        for (&local_dof, chunk) in izip!(&self.send_positions, contributions.chunks(chunk_size)) {
+            accumulate(&mut owned_values, &mut owned_touched, local_dof, chunk);
+        }
 
-        output_data
+        owned_values
     }
 
     /// Return the index layout
-    pub fn index_layout(&self) -> Rc<IndexLayout<'a, C>> {
+    pub fn index_layout(&self) -> Rc<L> {
         self.index_layout.clone()
     }
 
     /// Return the ghost communicator
-    pub fn ghost_communicator(&self) -> &crate::GhostCommunicator<usize> {
+    pub fn ghost_communicator(&self) -> &crate::GhostCommunicator<'a, usize, C> {
         &self.ghost_communicator
     }
 }
@@ -0,0 +1,141 @@
+//! Error types.
+
+use std::fmt;
+
+use itertools::Itertools;
+use mpi::collective::SystemOperation;
+use mpi::traits::{Communicator, CommunicatorCollectives};
+
+/// Errors returned by the distributed tools.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// An intra-communicator is required but an inter-communicator was given.
+    InterCommunicator,
+    /// The processes do not agree on an index layout or the layout is malformed.
+    InconsistentLayout,
+    /// An array has the wrong length.
+    SizeMismatch {
+        /// The required length.
+        expected: usize,
+        /// The given length.
+        actual: usize,
+    },
+    /// An index is not smaller than the number of indices.
+    IndexOutOfRange {
+        /// The index.
+        index: usize,
+        /// The number of indices.
+        bound: usize,
+    },
+    /// No process owns a global index.
+    UnknownOwner {
+        /// The global index.
+        index: usize,
+    },
+    /// A ghost index is owned by the current process.
+    OwnedGhostIndex {
+        /// The global index.
+        index: usize,
+    },
+    /// The arguments of a collective operation were invalid on other processes.
+    FailedOnOtherRanks {
+        /// The ranks of the processes with invalid arguments.
+        ranks: Vec<usize>,
+    },
+    /// An MPI call returned an error code.
+    ///
+    /// MPI only returns error codes if the error handler of the communicator is
+    /// `MPI_ERRORS_RETURN`. With the default handler MPI aborts instead.
+    Mpi(i32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InterCommunicator => write!(
+                f,
+                "An intra-communicator is required but an inter-communicator was given."
+            ),
+            Error::InconsistentLayout => write!(
+                f,
+                "The index layout is malformed or differs between the processes."
+            ),
+            Error::SizeMismatch { expected, actual } => write!(
+                f,
+                "Expected an array of length {} but got length {}.",
+                expected, actual
+            ),
+            Error::IndexOutOfRange { index, bound } => {
+                write!(f, "Index {} out of bounds for {} indices.", index, bound)
+            }
+            Error::UnknownOwner { index } => {
+                write!(f, "No process owns the global index {}.", index)
+            }
+            Error::OwnedGhostIndex { index } => {
+                write!(f, "Ghost index {} is owned by the current process.", index)
+            }
+            Error::FailedOnOtherRanks { ranks } => {
+                write!(
+                    f,
+                    "Invalid arguments on the processes with ranks {:?}.",
+                    ranks
+                )
+            }
+            Error::Mpi(code) => write!(f, "MPI call failed with error code {}.", code),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Return an error if an array does not have the expected length.
+pub(crate) fn check_size(expected: usize, actual: usize) -> Result<(), Error> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(Error::SizeMismatch { expected, actual })
+    }
+}
+
+/// Return an error if an MPI call did not succeed.
+pub(crate) fn check_mpi(code: i32) -> Result<(), Error> {
+    if code == mpi_sys::MPI_SUCCESS as i32 {
+        Ok(())
+    } else {
+        Err(Error::Mpi(code))
+    }
+}
+
+/// Return the ranks of all processes for which `failed` is true.
+///
+/// This is a collective operation. The processes first agree on whether any process failed with
+/// a reduction of a single flag. The failed ranks are only gathered if there is a failure.
+pub(crate) fn failed_ranks<C: Communicator>(comm: &C, failed: bool) -> Vec<usize> {
+    let mut any_failed = 0;
+    comm.all_reduce_into(&(failed as i32), &mut any_failed, SystemOperation::max());
+    if any_failed == 0 {
+        return Vec::new();
+    }
+
+    let mut failed_flags = vec![false; comm.size() as usize];
+    comm.all_gather_into(&failed, &mut failed_flags);
+    failed_flags.iter().positions(|&failed| failed).collect()
+}
+
+/// Agree on the result of the argument checks of a collective operation.
+///
+/// Returns the local error if there is one and [Error::FailedOnOtherRanks] if the checks
+/// failed on any other process. This allows all processes to return before the collective
+/// operation instead of waiting for a process that returned early.
+pub(crate) fn agree_collectively<C: Communicator>(
+    comm: &C,
+    result: Result<(), Error>,
+) -> Result<(), Error> {
+    let ranks = failed_ranks(comm, result.is_err());
+    result?;
+    if ranks.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::FailedOnOtherRanks { ranks })
+    }
+}
@@ -0,0 +1,294 @@
+//! Batches of fields that are exchanged together.
+//!
+//! Data mappers and permutations often move several fields over the same communication
+//! pattern, e.g. coordinates, normals and material ids of the same points. A [FieldBatch]
+//! collects the input and output slices of several fields. The fields may have different
+//! value types and chunk sizes. The values of all fields for one index are packed into a
+//! single record of bytes, so that all fields are exchanged with one message per neighbour.
+//!
+//! The values are packed with `MPI_Pack` and unpacked with `MPI_Unpack` using the MPI
+//! datatypes of the value types. Only the data described by the datatypes is read, so value
+//! types with padding and derived datatypes are supported. The records are exchanged with the
+//! `MPI_PACKED` datatype.
+
+use std::os::raw::c_void;
+
+use mpi::datatype::{DatatypeRef, SystemDatatype};
+use mpi::raw::FromRaw;
+use mpi::traits::{AsRaw, Communicator, Equivalence};
+
+extern "C" {
+    // Defined in `mpi_constants.c`.
+    static BEMPP_MPI_PACKED: mpi_sys::MPI_Datatype;
+}
+
+/// A byte of packed records, which is sent as `MPI_PACKED`.
+#[derive(Clone, Copy, Default)]
+#[repr(transparent)]
+pub(crate) struct PackedByte(u8);
+
+unsafe impl Equivalence for PackedByte {
+    type Out = SystemDatatype;
+
+    fn equivalent_datatype() -> Self::Out {
+        unsafe { DatatypeRef::from_raw(BEMPP_MPI_PACKED) }
+    }
+}
+
+/// A field of a batch with a fixed value type.
+trait BatchField {
+    /// The number of values of the input.
+    fn input_len(&self) -> usize;
+
+    /// The number of values of the output.
+    fn output_len(&self) -> usize;
+
+    /// The number of values per index.
+    fn chunk_size(&self) -> usize;
+
+    /// The number of bytes of the packed values of one index.
+    fn packed_size(&self, comm: mpi_sys::MPI_Comm) -> usize;
+
+    /// Pack the input values of an index into `record`, starting at byte `offset`.
+    fn pack(
+        &self,
+        position: usize,
+        record: &mut [PackedByte],
+        offset: usize,
+        comm: mpi_sys::MPI_Comm,
+    );
+
+    /// Unpack the values of an index from `record`, starting at byte `offset`, into the output.
+    fn unpack(
+        &mut self,
+        record: &[PackedByte],
+        offset: usize,
+        position: usize,
+        comm: mpi_sys::MPI_Comm,
+    );
+
+    /// Copy the input values of index `from` to the output values of index `to`.
+    fn copy(&mut self, from: usize, to: usize);
+}
+
+/// The input and output of a single field.
+struct Field<'f, T> {
+    input: &'f [T],
+    output: &'f mut [T],
+    chunk_size: usize,
+}
+
+impl<T: Equivalence + Copy> BatchField for Field<'_, T> {
+    fn input_len(&self) -> usize {
+        self.input.len()
+    }
+
+    fn output_len(&self) -> usize {
+        self.output.len()
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    fn packed_size(&self, comm: mpi_sys::MPI_Comm) -> usize {
+        let mut size = 0;
+        unsafe {
+            mpi_sys::MPI_Pack_size(
+                self.chunk_size as i32,
+                <T as Equivalence>::equivalent_datatype().as_raw(),
+                comm,
+                &mut size,
+            );
+        }
+        size as usize
+    }
+
+    fn pack(
+        &self,
+        position: usize,
+        record: &mut [PackedByte],
+        offset: usize,
+        comm: mpi_sys::MPI_Comm,
+    ) {
+        let chunk = &self.input[self.chunk_size * position..self.chunk_size * (1 + position)];
+        let mut offset = offset as i32;
+        unsafe {
+            mpi_sys::MPI_Pack(
+                chunk.as_ptr() as *const c_void,
+                self.chunk_size as i32,
+                <T as Equivalence>::equivalent_datatype().as_raw(),
+                record.as_mut_ptr() as *mut c_void,
+                record.len() as i32,
+                &mut offset,
+                comm,
+            );
+        }
+    }
+
+    fn unpack(
+        &mut self,
+        record: &[PackedByte],
+        offset: usize,
+        position: usize,
+        comm: mpi_sys::MPI_Comm,
+    ) {
+        let chunk = &mut self.output[self.chunk_size * position..self.chunk_size * (1 + position)];
+        let mut offset = offset as i32;
+        unsafe {
+            mpi_sys::MPI_Unpack(
+                record.as_ptr() as *const c_void,
+                record.len() as i32,
+                &mut offset,
+                chunk.as_mut_ptr() as *mut c_void,
+                self.chunk_size as i32,
+                <T as Equivalence>::equivalent_datatype().as_raw(),
+                comm,
+            );
+        }
+    }
+
+    fn copy(&mut self, from: usize, to: usize) {
+        self.output[self.chunk_size * to..self.chunk_size * (1 + to)]
+            .copy_from_slice(&self.input[self.chunk_size * from..self.chunk_size * (1 + from)]);
+    }
+}
+
+/// A batch of fields for a combined exchange.
+///
+/// Each field consists of an input slice, an output slice and the number of values per index.
+#[derive(Default)]
+pub struct FieldBatch<'f> {
+    fields: Vec<Box<dyn BatchField + 'f>>,
+}
+
+/// The positions of the packed fields within a record.
+pub(crate) struct RecordLayout {
+    offsets: Vec<usize>,
+    comm: mpi_sys::MPI_Comm,
+}
+
+impl RecordLayout {
+    /// The number of bytes of a record.
+    pub(crate) fn record_size(&self) -> usize {
+        *self.offsets.last().unwrap()
+    }
+
+    /// Create a zeroed buffer for `count` records.
+    pub(crate) fn records(&self, count: usize) -> Vec<PackedByte> {
+        vec![PackedByte::default(); count * self.record_size()]
+    }
+}
+
+impl<'f> FieldBatch<'f> {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a field with `chunk_size` values per index.
+    pub fn add<T: Equivalence + Copy + 'f>(
+        &mut self,
+        input: &'f [T],
+        output: &'f mut [T],
+        chunk_size: usize,
+    ) -> &mut Self {
+        self.fields.push(Box::new(Field {
+            input,
+            output,
+            chunk_size,
+        }));
+        self
+    }
+
+    /// The number of fields.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Return true if the batch contains no fields.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Compute the positions of the fields within a record packed for `comm`.
+    pub(crate) fn record_layout<C: Communicator>(&self, comm: &C) -> RecordLayout {
+        let comm = comm.as_raw();
+        let offsets = std::iter::once(0)
+            .chain(self.fields.iter().scan(0, |acc, field| {
+                *acc += field.packed_size(comm);
+                Some(*acc)
+            }))
+            .collect();
+        RecordLayout { offsets, comm }
+    }
+
+    /// Check that all inputs have `input_count` and all outputs `output_count` indices.
+    pub(crate) fn assert_counts(&self, input_count: usize, output_count: usize) {
+        for (index, field) in self.fields.iter().enumerate() {
+            assert_eq!(
+                field.input_len(),
+                input_count * field.chunk_size(),
+                "Input of field {} has the wrong length.",
+                index
+            );
+            assert_eq!(
+                field.output_len(),
+                output_count * field.chunk_size(),
+                "Output of field {} has the wrong length.",
+                index
+            );
+        }
+    }
+
+    /// Pack the inputs of all fields at the given positions into consecutive records.
+    pub(crate) fn pack(
+        &self,
+        layout: &RecordLayout,
+        positions: impl ExactSizeIterator<Item = usize>,
+    ) -> Vec<PackedByte> {
+        let record_size = layout.record_size();
+        let mut records = layout.records(positions.len());
+        for (record, position) in records.chunks_mut(record_size.max(1)).zip(positions) {
+            for (field, &offset) in self.fields.iter().zip(&layout.offsets) {
+                field.pack(position, record, offset, layout.comm);
+            }
+        }
+        records
+    }
+
+    /// Unpack consecutive records into the outputs of all fields at the given positions.
+    pub(crate) fn unpack(
+        &mut self,
+        layout: &RecordLayout,
+        records: &[PackedByte],
+        positions: impl Iterator<Item = usize>,
+    ) {
+        let record_size = layout.record_size();
+        if record_size == 0 {
+            return;
+        }
+        for (record, position) in records.chunks(record_size).zip(positions) {
+            self.unpack_record(layout, record, position);
+        }
+    }
+
+    /// Unpack a single record into the outputs of all fields at the given position.
+    pub(crate) fn unpack_record(
+        &mut self,
+        layout: &RecordLayout,
+        record: &[PackedByte],
+        position: usize,
+    ) {
+        for (field, &offset) in self.fields.iter_mut().zip(&layout.offsets) {
+            field.unpack(record, offset, position, layout.comm);
+        }
+    }
+
+    /// Copy the inputs of all fields at position `from` to the outputs at position `to`.
+    pub(crate) fn copy(&mut self, from: usize, to: usize) {
+        for field in &mut self.fields {
+            field.copy(from, to);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Index layouts with arbitrary ownership.
+//!
+//! A [GeneralIndexLayout] allows each process to own an arbitrary set of global indices, e.g.
+//! the output of a mesh partitioner. The owner of an index cannot be computed locally. Instead,
+//! each index is assigned to a rendezvous process by hashing. The rendezvous process stores
+//! the owning rank and the local index of the index in a distributed directory. Owner queries
+//! are sent to the rendezvous processes and are therefore collective operations. Queries for
+//! a single index are thin wrappers around the batched queries, see
+//! [GeneralIndexLayout::locations_from_indices], and should be avoided in loops.
+
+use std::collections::HashMap;
+
+use itertools::{izip, Itertools};
+use mpi::traits::{Communicator, CommunicatorCollectives};
+
+use crate::array_tools::all_to_allv;
+use crate::comm_ref::CommRef;
+use crate::error::Error;
+use crate::layout::Layout;
+
+/// An index layout with arbitrary sets of owned indices.
+///
+/// The owned indices of all processes must partition the global indices `0..n`.
+pub struct GeneralIndexLayout<'a, C: Communicator> {
+    owned_indices: Vec<usize>,
+    global_to_local: HashMap<usize, usize>,
+    number_of_global_indices: usize,
+    // Owning rank and local index of the indices for which this process is the
+    // rendezvous process.
+    directory: HashMap<usize, (usize, usize)>,
+    comm: CommRef<'a, C>,
+}
+
+impl<C: Communicator> std::fmt::Debug for GeneralIndexLayout<'_, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "GeneralIndexLayout with {} global indices and {} local indices.",
+            self.number_of_global_indices,
+            self.owned_indices.len()
+        )
+    }
+}
+
+impl<'a, C: Communicator> GeneralIndexLayout<'a, C> {
+    /// Create a new general index layout.
+    ///
+    /// `owned_indices` are the global indices owned by the current process. The local index of
+    /// `owned_indices[i]` is `i`. The communicator can be borrowed or shared, see [CommRef].
+    /// This is a collective operation.
+    pub fn new(owned_indices: Vec<usize>, comm: impl Into<CommRef<'a, C>>) -> Self {
+        let comm = comm.into();
+        let size = comm.size() as usize;
+
+        let mut number_of_global_indices = 0;
+        comm.all_reduce_into(
+            &owned_indices.len(),
+            &mut number_of_global_indices,
+            mpi::collective::SystemOperation::sum(),
+        );
+
+        let global_to_local = HashMap::<usize, usize>::from_iter(
+            owned_indices
+                .iter()
+                .enumerate()
+                .map(|(local_index, &global_index)| (global_index, local_index)),
+        );
+
+        // Register the owned indices with their rendezvous processes.
+
+        let sorted_local_indices = (0..owned_indices.len())
+            .sorted_by_key(|&local_index| rendezvous_rank(owned_indices[local_index], size))
+            .collect_vec();
+
+        let mut counts = vec![0; size];
+        for &local_index in &sorted_local_indices {
+            counts[rendezvous_rank(owned_indices[local_index], size)] += 1;
+        }
+
+        let sorted_global_indices = sorted_local_indices
+            .iter()
+            .map(|&local_index| owned_indices[local_index])
+            .collect_vec();
+
+        let (receive_counts, registered_global_indices) =
+            all_to_allv(&*comm, &counts, &sorted_global_indices);
+        let (_, registered_local_indices) = all_to_allv(&*comm, &counts, &sorted_local_indices);
+
+        let registered_ranks = receive_counts
+            .iter()
+            .enumerate()
+            .flat_map(|(rank, &count)| std::iter::repeat_n(rank, count));
+
+        let directory = HashMap::<usize, (usize, usize)>::from_iter(
+            izip!(
+                registered_global_indices,
+                registered_ranks,
+                registered_local_indices
+            )
+            .map(|(global_index, rank, local_index)| (global_index, (rank, local_index))),
+        );
+
+        Self {
+            owned_indices,
+            global_to_local,
+            number_of_global_indices,
+            directory,
+            comm,
+        }
+    }
+
+    /// Return the global indices owned by the current process in local order.
+    pub fn owned_indices(&self) -> &[usize] {
+        &self.owned_indices
+    }
+
+    /// The number of global indices.
+    pub fn number_of_global_indices(&self) -> usize {
+        self.number_of_global_indices
+    }
+
+    /// The number of indices owned by the current process.
+    pub fn number_of_local_indices(&self) -> usize {
+        self.owned_indices.len()
+    }
+
+    /// Convert a local index to a global index.
+    ///
+    /// Returns `None` if `index` is out of bounds.
+    pub fn local2global(&self, index: usize) -> Option<usize> {
+        self.owned_indices.get(index).copied()
+    }
+
+    /// Convert a global index to a local index on a given rank.
+    ///
+    /// Returns `None` if the index does not exist on the rank. This is a collective operation
+    /// that must be called on all processes together. Use
+    /// [GeneralIndexLayout::locations_from_indices] to convert several indices at once.
+    pub fn global2local(&self, rank: usize, index: usize) -> Option<usize> {
+        self.locations_from_indices(&[index])[0]
+            .filter(|&(owner, _)| owner == rank)
+            .map(|(_, local_index)| local_index)
+    }
+
+    /// Get the rank of a given index.
+    ///
+    /// Returns `None` if the index is out of bounds. This is a collective operation that must
+    /// be called on all processes together. Use [GeneralIndexLayout::ranks_from_indices] to
+    /// query several indices at once.
+    pub fn rank_from_index(&self, index: usize) -> Option<usize> {
+        self.locations_from_indices(&[index])[0].map(|(rank, _)| rank)
+    }
+
+    /// Get the ranks of several indices.
+    ///
+    /// Panics if an index is out of bounds. This is a collective operation.
+    pub fn ranks_from_indices(&self, indices: &[usize]) -> Vec<usize> {
+        self.try_ranks_from_indices(indices)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Get the ranks of several indices.
+    ///
+    /// Returns [Error::UnknownOwner] for the first index that is not owned by any process.
+    /// This is a collective operation. The lookups are completed on all processes before the
+    /// error is returned.
+    pub fn try_ranks_from_indices(&self, indices: &[usize]) -> Result<Vec<usize>, Error> {
+        izip!(indices, self.locations_from_indices(indices))
+            .map(|(&index, location)| {
+                location
+                    .map(|(rank, _)| rank)
+                    .ok_or(Error::UnknownOwner { index })
+            })
+            .collect()
+    }
+
+    /// Get the owning ranks and local indices of several global indices.
+    ///
+    /// An entry is `None` if the corresponding index is out of bounds. This is a collective
+    /// operation.
+    pub fn locations_from_indices(&self, indices: &[usize]) -> Vec<Option<(usize, usize)>> {
+        let size = self.comm.size() as usize;
+
+        // Send the queries to the rendezvous processes.
+
+        let sorted_positions = (0..indices.len())
+            .sorted_by_key(|&position| rendezvous_rank(indices[position], size))
+            .collect_vec();
+
+        let mut counts = vec![0; size];
+        for &position in &sorted_positions {
+            counts[rendezvous_rank(indices[position], size)] += 1;
+        }
+
+        let sorted_indices = sorted_positions
+            .iter()
+            .map(|&position| indices[position])
+            .collect_vec();
+
+        let (receive_counts, queries) = all_to_allv(self.comm(), &counts, &sorted_indices);
+
+        // Answer the queries from the directory. Unknown indices are marked with `usize::MAX`.
+
+        let (answer_ranks, answer_local_indices): (Vec<usize>, Vec<usize>) = queries
+            .iter()
+            .map(|index| {
+                self.directory
+                    .get(index)
+                    .copied()
+                    .unwrap_or((usize::MAX, usize::MAX))
+            })
+            .unzip();
+
+        let (_, ranks) = all_to_allv(self.comm(), &receive_counts, &answer_ranks);
+        let (_, local_indices) = all_to_allv(self.comm(), &receive_counts, &answer_local_indices);
+
+        // The answers arrive in the order of the sorted queries.
+
+        let mut locations = vec![None; indices.len()];
+        for (&position, rank, local_index) in izip!(&sorted_positions, ranks, local_indices) {
+            if rank != usize::MAX {
+                locations[position] = Some((rank, local_index));
+            }
+        }
+
+        locations
+    }
+
+    /// Return the communicator.
+    pub fn comm(&self) -> &C {
+        &self.comm
+    }
+
+    /// Return the borrowed or shared communicator.
+    pub fn comm_ref(&self) -> &CommRef<'a, C> {
+        &self.comm
+    }
+}
+
+impl<'a, C: Communicator> Layout<'a> for GeneralIndexLayout<'a, C> {
+    type Comm = C;
+
+    fn comm_ref(&self) -> &CommRef<'a, C> {
+        &self.comm
+    }
+
+    fn comm(&self) -> &C {
+        &self.comm
+    }
+
+    fn number_of_global_indices(&self) -> usize {
+        self.number_of_global_indices
+    }
+
+    fn number_of_local_indices(&self) -> usize {
+        self.owned_indices.len()
+    }
+
+    fn local_range(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    fn local2global(&self, index: usize) -> Option<usize> {
+        GeneralIndexLayout::local2global(self, index)
+    }
+
+    fn global2local_owned(&self, index: usize) -> Option<usize> {
+        self.global_to_local.get(&index).copied()
+    }
+
+    fn ranks_from_indices(&self, indices: &[usize]) -> Vec<usize> {
+        GeneralIndexLayout::ranks_from_indices(self, indices)
+    }
+
+    fn try_ranks_from_indices(&self, indices: &[usize]) -> Result<Vec<usize>, Error> {
+        GeneralIndexLayout::try_ranks_from_indices(self, indices)
+    }
+}
+
+/// Return the rendezvous process of a global index.
+///
+/// The index is hashed first, so that blocks of consecutive indices are spread evenly
+/// over the processes.
+fn rendezvous_rank(index: usize, size: usize) -> usize {
+    // The finaliser of the SplitMix64 generator.
+    let mut hash = index as u64;
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^= hash >> 31;
+    (hash % size as u64) as usize
+}
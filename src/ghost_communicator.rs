@@ -9,14 +9,88 @@
 //! # Example
 //!
 //! A fully worked example is provided in the file `examples/ghost_communicator.rs`.
+//!
+//! # Backends
+//!
+//! By default the data exchange uses MPI neighbourhood collectives on distributed graph
+//! communicators. Since some MPI implementations have slow or unreliable neighbourhood
+//! collectives, a point-to-point backend based on `MPI_Isend`/`MPI_Irecv` pairs can be
+//! selected at construction time via [GhostCommunicatorOptions].
+//!
+//! # Setup
+//!
+//! The owners of the ghosts are by default informed about the ghosts with a dense all-to-all
+//! exchange. For very large numbers of processes with few neighbours each, the sparse
+//! consensus setup from [GhostCommunicatorSetup::SparseConsensus] should be used instead.
 
+use std::collections::HashMap;
+use std::hash::Hash;
 use std::os::raw::c_void;
 
+use itertools::{izip, Itertools};
+
+use mpi::request::Scope;
 use mpi::topology::SimpleCommunicator;
-use mpi::traits::{AsRaw, Communicator, CommunicatorCollectives, Equivalence, FromRaw};
+use mpi::traits::{
+    AsRaw, Communicator, CommunicatorCollectives, Destination, Equivalence, FromRaw, Source,
+};
+
+use crate::array_tools::displacements;
+use crate::comm_ref::CommRef;
+use crate::error::{agree_collectively, check_mpi, Error};
+use crate::layout::Layout;
+use crate::reduction::{combine_slices, ReductionOp};
+use crate::request::ExchangeRequest;
+
+/// The backend used for the data exchange of a [GhostCommunicator].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GhostCommunicatorBackend {
+    /// Exchange data with MPI neighbourhood collectives on distributed graph communicators.
+    #[default]
+    NeighbourhoodCollective,
+    /// Exchange data with non-blocking point-to-point messages.
+    PointToPoint,
+}
+
+/// The algorithm used to discover the communication pattern of a [GhostCommunicator].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GhostCommunicatorSetup {
+    /// Exchange the number of ghosts between all pairs of processes with a dense
+    /// `MPI_Alltoall`. Memory and communication scale with the size of the communicator.
+    #[default]
+    AllToAll,
+    /// Discover the owners that need to send data with the non-blocking consensus algorithm
+    /// based on `MPI_Issend` and `MPI_Ibarrier`. Memory and communication scale with the
+    /// number of neighbours.
+    SparseConsensus,
+}
+
+/// Options for the construction of a [GhostCommunicator].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GhostCommunicatorOptions {
+    /// The backend used for the data exchange.
+    pub backend: GhostCommunicatorBackend,
+    /// The algorithm used to discover the communication pattern.
+    pub setup: GhostCommunicatorSetup,
+    /// Pass the number of indices exchanged with each neighbour as edge weights to the
+    /// graph communicators. Only used by the neighbourhood collective backend.
+    pub weighted: bool,
+    /// Ask MPI for a reordering of the ranks that fits the communication pattern. The
+    /// exchanges still use the original ranks. Only used by the neighbourhood collective
+    /// backend. See [GhostCommunicator::rank_permutation].
+    pub reorder: bool,
+}
 
 /// Ghost communicator
-pub struct GhostCommunicator<I: Default + Copy + Equivalence> {
+///
+/// The parent communicator from which the ghost communicator was created can be borrowed or
+/// shared, see [CommRef]. The forward and backward communicators are owned by the ghost
+/// communicator and freed when it is dropped.
+pub struct GhostCommunicator<
+    'a,
+    I: Default + Copy + Equivalence,
+    C: Communicator = SimpleCommunicator,
+> {
     /// The `out` ranks that data is sent to from the current process.
     pub out_ranks: Vec<i32>,
     /// The `in` ranks that send data to the current process.
@@ -37,137 +111,206 @@ pub struct GhostCommunicator<I: Default + Copy + Equivalence> {
     pub total_send_count: usize,
     /// Total number of items to receive
     pub total_receive_count: usize,
-    ///  The forward communicator
+    /// The forward communicator
     pub forward_comm: SimpleCommunicator,
     /// The backward communicator that reverses the `in` and `out` vertices
     pub backward_comm: SimpleCommunicator,
+    /// The parent communicator
+    comm: CommRef<'a, C>,
+    /// The ranks in the forward communicator of all processes if ranks were reordered
+    rank_permutation: Option<Vec<usize>>,
+    /// The backend used for the data exchange
+    pub backend: GhostCommunicatorBackend,
+    /// Map from receive indices to their positions. Only set up by
+    /// [GhostCommunicator::from_layout].
+    receive_positions: HashMap<I, usize>,
+    /// Local indices of the send indices in the index layout. Only set up by
+    /// [GhostCommunicator::from_layout].
+    send_positions: Option<Vec<usize>>,
 }
 
-impl<I: Default + Copy + Equivalence> GhostCommunicator<I> {
+impl<'a, I: Default + Copy + Equivalence, C: Communicator> GhostCommunicator<'a, I, C> {
     /// Create new ghost communicator.
     ///
     /// # Arguments
     /// - `ghost_indices` - The ghost indices required on the current process.
     /// - `owning_ranks` - The ranks of the processes that own the ghost indices.
-    /// - `comm` - The MPI communicator.
-    pub fn new<C: Communicator>(
+    /// - `comm` - The MPI communicator, borrowed or shared, see [CommRef].
+    ///
+    /// # Panics
+    /// Panics if `comm` is an inter-communicator. Use [GhostCommunicator::try_new] to handle
+    /// this case.
+    pub fn new(
+        ghost_indices: &[I],
+        owning_ranks: &[usize],
+        comm: impl Into<CommRef<'a, C>>,
+    ) -> Self {
+        Self::new_with_options(
+            ghost_indices,
+            owning_ranks,
+            comm,
+            GhostCommunicatorOptions::default(),
+        )
+    }
+
+    /// Create new ghost communicator with the given options.
+    ///
+    /// # Arguments
+    /// - `ghost_indices` - The ghost indices required on the current process.
+    /// - `owning_ranks` - The ranks of the processes that own the ghost indices.
+    /// - `comm` - The MPI communicator, borrowed or shared, see [CommRef].
+    /// - `options` - The options of the ghost communicator, e.g. the backend.
+    ///
+    /// # Panics
+    /// Panics if `comm` is an inter-communicator. Use [GhostCommunicator::try_new_with_options]
+    /// to handle this case.
+    pub fn new_with_options(
+        ghost_indices: &[I],
+        owning_ranks: &[usize],
+        comm: impl Into<CommRef<'a, C>>,
+        options: GhostCommunicatorOptions,
+    ) -> Self {
+        Self::try_new_with_options(ghost_indices, owning_ranks, comm, options)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Create new ghost communicator.
+    ///
+    /// Returns [Error::InterCommunicator] if `comm` is an inter-communicator and [Error::Mpi]
+    /// if MPI reports an error. See [GhostCommunicator::new] for a description of the arguments.
+    pub fn try_new(
+        ghost_indices: &[I],
+        owning_ranks: &[usize],
+        comm: impl Into<CommRef<'a, C>>,
+    ) -> Result<Self, Error> {
+        Self::try_new_with_options(
+            ghost_indices,
+            owning_ranks,
+            comm,
+            GhostCommunicatorOptions::default(),
+        )
+    }
+
+    /// Create new ghost communicator with the given options.
+    ///
+    /// Returns [Error::InterCommunicator] if `comm` is an inter-communicator and [Error::Mpi]
+    /// if MPI reports an error. See [GhostCommunicator::new_with_options] for a description of the arguments.
+    pub fn try_new_with_options(
         ghost_indices: &[I],
         owning_ranks: &[usize],
-        comm: &C,
-    ) -> GhostCommunicator<I> {
-        // Get the processes of global indices and create a map rank -> indices_on_rank
+        comm: impl Into<CommRef<'a, C>>,
+        options: GhostCommunicatorOptions,
+    ) -> Result<Self, Error> {
+        let comm = comm.into();
 
-        let mut receive_counts = vec![0; comm.size() as usize];
+        // Distributed graph communicators and the setup collectives require an
+        // intra-communicator.
 
-        for &rank in owning_ranks {
-            receive_counts[rank] += 1;
+        if comm.test_inter() {
+            return Err(Error::InterCommunicator);
         }
 
-        // Now sort the ghost indices by ranks
-        // These are the receive indices, meaning the indices that we are receiving on the process.
+        #[cfg(feature = "checked")]
+        crate::check::check_owning_ranks(ghost_indices.len(), owning_ranks, &*comm);
 
-        let receive_indices = {
-            let mut sorted_ghost_index_args = (0..ghost_indices.len()).collect::<Vec<_>>();
-            sorted_ghost_index_args.sort_by_key(|&i| owning_ranks[i]);
+        // Sort the ghost indices by ranks. These are the receive indices, meaning the
+        // indices that we are receiving on the process.
 
-            let mut global_indices_t = Vec::<I>::with_capacity(ghost_indices.len());
-            for arg in sorted_ghost_index_args {
-                global_indices_t.push(ghost_indices[arg]);
+        let mut sorted_ghost_index_args = (0..ghost_indices.len()).collect::<Vec<_>>();
+        sorted_ghost_index_args.sort_by_key(|&i| owning_ranks[i]);
+
+        let receive_indices = sorted_ghost_index_args
+            .iter()
+            .map(|&arg| ghost_indices[arg])
+            .collect::<Vec<_>>();
+
+        // Get the in-neighbours, i.e. the ranks from which we receive data, together with
+        // the number of indices received from each of them.
+
+        let mut in_ranks = Vec::<i32>::new();
+        let mut receive_counts = Vec::<i32>::new();
+
+        for &arg in &sorted_ghost_index_args {
+            let rank = owning_ranks[arg] as i32;
+            if in_ranks.last() != Some(&rank) {
+                in_ranks.push(rank);
+                receive_counts.push(0);
             }
-            global_indices_t
-        };
+            *receive_counts.last_mut().unwrap() += 1;
+        }
 
         // We have now completed setting up the data on the ghost receivers. We now need
-        // to tell the original owners of the ghosts who needs their data and setup the
-        // communication structures. For this we first communicate the number indices via
-        // an all_to_all.
+        // to tell the original owners of the ghosts who needs their data. This gives us the
+        // out-neighbours, i.e. the ranks to which we send data, and the send counts.
+
+        let (out_ranks, send_counts) = match options.setup {
+            GhostCommunicatorSetup::AllToAll => {
+                dense_exchange_counts(&in_ranks, &receive_counts, &*comm)
+            }
+            GhostCommunicatorSetup::SparseConsensus => {
+                sparse_exchange_counts(&in_ranks, &receive_counts, &*comm)
+            }
+        };
 
-        let mut send_counts = vec![0; comm.size() as usize];
-        comm.all_to_all_into(&receive_counts, &mut send_counts);
+        let receive_displacements = displacements(&receive_counts);
+        let send_displacements = displacements(&send_counts);
 
-        // Each process now has a list of ranks from which it receives and a list of indices
-        // to which it sends. We now create a neighborhood communicator across all the ranks
-        // from which a rank sends or receives
+        let (forward_comm, backward_comm) = match options.backend {
+            GhostCommunicatorBackend::NeighbourhoodCollective => {
+                // With weights, each edge is weighted by the number of indices sent along it.
 
-        // The following loop creates the neighbors, the receive displacements and the send
-        // displacements.
+                let receive_weights = options.weighted.then_some(&receive_counts[..]);
+                let send_weights = options.weighted.then_some(&send_counts[..]);
 
-        let mut out_ranks = Vec::<i32>::new();
-        let mut in_ranks = Vec::<i32>::new();
-        let mut receive_displacements = Vec::<i32>::new();
-        let mut send_displacements = Vec::<i32>::new();
-
-        // Get the in neighbours and the out-neighbours
-        // for the neighborhood communicators. in-neighbors
-        // are the ranks from which we receive data and out-neighbors
-        // are the ranks to which we send data.
-
-        let mut total_send_count = 0;
-        let mut total_receive_count = 0;
-
-        // We restrict the send and receive counts to the neighbors
-        // and also compute the corresponding displacements.
-
-        let (receive_counts, send_counts) = {
-            let mut neighbor_receive_counts = Vec::<i32>::new();
-            let mut neighbor_send_counts = Vec::<i32>::new();
-
-            for index in 0..comm.size() as usize {
-                if receive_counts[index] != 0 {
-                    in_ranks.push(index as i32);
-                    neighbor_receive_counts.push(receive_counts[index]);
-                    receive_displacements.push(total_receive_count);
-                    total_receive_count += receive_counts[index];
-                }
-                if send_counts[index] != 0 {
-                    neighbor_send_counts.push(send_counts[index]);
-                    out_ranks.push(index as i32);
-                    send_displacements.push(total_send_count);
-                    total_send_count += send_counts[index];
-                }
+                // The exchanges always use the ranks of the parent communicator. The
+                // backward communicator simply reverses the in and out neighbours.
+
+                let forward_comm = create_graph_comm(
+                    &*comm,
+                    (&in_ranks, receive_weights),
+                    (&out_ranks, send_weights),
+                    false,
+                )?;
+                let backward_comm = create_graph_comm(
+                    &*comm,
+                    (&out_ranks, send_weights),
+                    (&in_ranks, receive_weights),
+                    false,
+                )?;
+
+                (forward_comm, backward_comm)
+            }
+            GhostCommunicatorBackend::PointToPoint => {
+                // Point-to-point messages go over duplicates of the parent communicator so that
+                // they cannot be matched by other messages on the parent communicator or by
+                // messages of the other direction.
+                (comm.duplicate(), comm.duplicate())
             }
-            (neighbor_receive_counts, neighbor_send_counts)
         };
 
-        // To create the actual communicator need to call into mpi-sys as not yet wrapped into
-        // higher level interface.
-
-        let forward_comm = unsafe {
-            let mut raw_comm = mpi_sys::RSMPI_COMM_NULL;
-            mpi_sys::MPI_Dist_graph_create_adjacent(
-                comm.as_raw(),
-                in_ranks.len() as i32,
-                in_ranks.as_ptr(),
-                mpi_sys::RSMPI_UNWEIGHTED(),
-                out_ranks.len() as i32,
-                out_ranks.as_ptr(),
-                mpi_sys::RSMPI_UNWEIGHTED(),
-                mpi_sys::RSMPI_INFO_NULL,
-                0,
-                &mut raw_comm,
-            );
-
-            mpi::topology::SimpleCommunicator::from_raw(raw_comm)
-        };
+        // If MPI was allowed to reorder the ranks, a reordered graph communicator is created
+        // only to find the new ranks, which every process needs to know. The exchanges keep
+        // using the communicators with the original ranks, whose neighbours match the counts
+        // and indices of this process.
 
-        // The backward communicator simply reverses the in and out neighbors.
-
-        let backward_comm = unsafe {
-            let mut raw_comm = mpi_sys::RSMPI_COMM_NULL;
-            mpi_sys::MPI_Dist_graph_create_adjacent(
-                comm.as_raw(),
-                out_ranks.len() as i32,
-                out_ranks.as_ptr(),
-                mpi_sys::RSMPI_UNWEIGHTED(),
-                in_ranks.len() as i32,
-                in_ranks.as_ptr(),
-                mpi_sys::RSMPI_UNWEIGHTED(),
-                mpi_sys::RSMPI_INFO_NULL,
-                0,
-                &mut raw_comm,
-            );
-
-            mpi::topology::SimpleCommunicator::from_raw(raw_comm)
+        let rank_permutation = if options.reorder
+            && options.backend == GhostCommunicatorBackend::NeighbourhoodCollective
+        {
+            let reordered_comm = create_graph_comm(
+                &*comm,
+                (&in_ranks, options.weighted.then_some(&receive_counts[..])),
+                (&out_ranks, options.weighted.then_some(&send_counts[..])),
+                true,
+            )?;
+            let mut new_ranks = vec![0; comm.size() as usize];
+            comm.all_gather_into(&reordered_comm.rank(), &mut new_ranks);
+
+            // The reordered communicator is freed here as it is not needed any more.
+            drop(reordered_comm);
+
+            Some(new_ranks.into_iter().map(|rank| rank as usize).collect())
+        } else {
+            None
         };
 
         // We now communicate the global indices back from the receivers to the senders.
@@ -175,29 +318,10 @@ impl<I: Default + Copy + Equivalence> GhostCommunicator<I> {
         let total_send_count = send_counts.iter().sum::<i32>() as usize;
         let total_receive_count = receive_counts.iter().sum::<i32>() as usize;
 
-        let mut send_indices = vec![<I as Default>::default(); total_send_count];
-
-        // The receivers know what indices they need from each process. But the
-        // senders don't know yet what indices to send to each process. So we
-        // send the receive indices back to the senders.
-
-        unsafe {
-            mpi_sys::MPI_Neighbor_alltoallv(
-                receive_indices.as_ptr() as *const c_void,
-                receive_counts.as_ptr(),
-                receive_displacements.as_ptr(),
-                <I as Equivalence>::equivalent_datatype().as_raw(),
-                send_indices.as_mut_ptr() as *mut c_void,
-                send_counts.as_ptr(),
-                send_displacements.as_ptr(),
-                <I as Equivalence>::equivalent_datatype().as_raw(),
-                backward_comm.as_raw(),
-            );
-        }
-        Self {
+        let mut ghost_communicator = Self {
             out_ranks,
             in_ranks,
-            send_indices,
+            send_indices: Vec::new(),
             receive_indices,
             send_counts,
             receive_counts,
@@ -207,7 +331,26 @@ impl<I: Default + Copy + Equivalence> GhostCommunicator<I> {
             total_receive_count,
             forward_comm,
             backward_comm,
-        }
+            comm,
+            rank_permutation,
+            backend: options.backend,
+            receive_positions: HashMap::new(),
+            send_positions: None,
+        };
+
+        // The receivers know what indices they need from each process. But the
+        // senders don't know yet what indices to send to each process. So we
+        // send the receive indices back to the senders.
+
+        let mut send_indices = vec![<I as Default>::default(); total_send_count];
+        ghost_communicator.backward_send_values_with_counts(
+            &ghost_communicator.receive_indices,
+            &mut send_indices,
+            &mut ghost_communicator.chunked_counts(1),
+        )?;
+        ghost_communicator.send_indices = send_indices;
+
+        Ok(ghost_communicator)
     }
 
     /// Return the ranks to which the current process sends to.
@@ -240,6 +383,16 @@ impl<I: Default + Copy + Equivalence> GhostCommunicator<I> {
         &self.receive_counts
     }
 
+    /// Return the displacements of the send counts.
+    pub fn send_displacements(&self) -> &[i32] {
+        &self.send_displacements
+    }
+
+    /// Return the displacements of the receive counts.
+    pub fn receive_displacements(&self) -> &[i32] {
+        &self.receive_displacements
+    }
+
     /// Return the total send count.
     pub fn total_send_count(&self) -> usize {
         self.total_send_count
@@ -265,27 +418,39 @@ impl<I: Default + Copy + Equivalence> GhostCommunicator<I> {
         &self.backward_comm
     }
 
+    /// Return the parent communicator.
+    pub fn comm(&self) -> &C {
+        &self.comm
+    }
+
+    /// Return the reordering of the ranks suggested by MPI.
+    ///
+    /// If the ghost communicator was created with [GhostCommunicatorOptions::reorder], MPI was
+    /// asked for new ranks that optimise the placement of the processes for the communication
+    /// pattern. The permutation maps old ranks to new ranks: entry `i` is the new rank of the
+    /// process with rank `i` in the parent communicator. The ghost communicator itself keeps
+    /// exchanging data with the old ranks. To benefit from the new placement, the data owned
+    /// by the process with old rank `i` should be moved to the process with rank
+    /// `rank_permutation()[i]` in the parent communicator, e.g. by remapping
+    /// [IndexLayout](crate::IndexLayout)s, and a new ghost communicator should be created for
+    /// the moved data.
+    ///
+    /// Returns `None` if the ranks were not reordered.
+    pub fn rank_permutation(&self) -> Option<&[usize]> {
+        self.rank_permutation.as_deref()
+    }
+
+    /// Return the backend used for the data exchange.
+    pub fn backend(&self) -> GhostCommunicatorBackend {
+        self.backend
+    }
+
     /// Forward send values.
     ///
     /// This updates ghosts on the receiver process with the values of the ghosts from
     /// their owning process.
     pub fn forward_send_values<T: Equivalence>(&self, out_values: &[T], in_values: &mut [T]) {
-        assert_eq!(in_values.len(), self.total_receive_count);
-        assert_eq!(out_values.len(), self.total_send_count);
-
-        unsafe {
-            mpi_sys::MPI_Neighbor_alltoallv(
-                out_values.as_ptr() as *const c_void,
-                self.send_counts.as_ptr(),
-                self.send_displacements.as_ptr(),
-                <I as Equivalence>::equivalent_datatype().as_raw(),
-                in_values.as_mut_ptr() as *mut c_void,
-                self.receive_counts.as_ptr(),
-                self.receive_displacements.as_ptr(),
-                <I as Equivalence>::equivalent_datatype().as_raw(),
-                self.forward_comm.as_raw(),
-            );
-        }
+        self.forward_send_values_by_chunks(out_values, in_values, 1);
     }
 
     /// Forward send values with a given chunk size.
@@ -299,42 +464,53 @@ impl<I: Default + Copy + Equivalence> GhostCommunicator<I> {
         in_values: &mut [T],
         chunk_size: usize,
     ) {
-        assert_eq!(in_values.len(), self.total_receive_count * chunk_size);
-        assert_eq!(out_values.len(), self.total_send_count * chunk_size);
+        self.forward_send_values_with_counts(
+            out_values,
+            in_values,
+            &mut self.chunked_counts(chunk_size),
+        )
+        .unwrap_or_else(|error| panic!("{}", error));
+    }
 
-        let chunked_send_counts = self
-            .send_counts
-            .iter()
-            .map(|&x| x * chunk_size as i32)
-            .collect::<Vec<_>>();
-        let chunked_receive_counts = self
-            .receive_counts
-            .iter()
-            .map(|&x| x * chunk_size as i32)
-            .collect::<Vec<_>>();
-        let chunked_send_displacements = self
-            .send_displacements
-            .iter()
-            .map(|&x| x * chunk_size as i32)
-            .collect::<Vec<_>>();
-        let chunked_receive_displacements = self
-            .receive_displacements
-            .iter()
-            .map(|&x| x * chunk_size as i32)
-            .collect::<Vec<_>>();
+    /// Forward send values with precomputed counts.
+    ///
+    /// Does the same exchange as [GhostCommunicator::forward_send_values_by_chunks] with the
+    /// chunk size of `counts`, but does not allocate. Returns [Error::Mpi] if MPI reports an
+    /// error.
+    pub(crate) fn forward_send_values_with_counts<T: Equivalence>(
+        &self,
+        out_values: &[T],
+        in_values: &mut [T],
+        counts: &mut ChunkedCounts,
+    ) -> Result<(), Error> {
+        assert_eq!(
+            in_values.len(),
+            self.total_receive_count * counts.chunk_size
+        );
+        assert_eq!(out_values.len(), self.total_send_count * counts.chunk_size);
 
-        unsafe {
-            mpi_sys::MPI_Neighbor_alltoallv(
-                out_values.as_ptr() as *const c_void,
-                chunked_send_counts.as_ptr(),
-                chunked_send_displacements.as_ptr(),
-                <I as Equivalence>::equivalent_datatype().as_raw(),
-                in_values.as_mut_ptr() as *mut c_void,
-                chunked_receive_counts.as_ptr(),
-                chunked_receive_displacements.as_ptr(),
-                <I as Equivalence>::equivalent_datatype().as_raw(),
-                self.forward_comm.as_raw(),
-            );
+        match self.backend {
+            GhostCommunicatorBackend::NeighbourhoodCollective => neighbor_alltoallv(
+                out_values,
+                &counts.send_counts,
+                &counts.send_displacements,
+                in_values,
+                &counts.receive_counts,
+                &counts.receive_displacements,
+                &self.forward_comm,
+            ),
+            GhostCommunicatorBackend::PointToPoint => blocking_point_to_point_exchange(
+                &mut counts.requests,
+                out_values,
+                &self.out_ranks,
+                &counts.send_counts,
+                &counts.send_displacements,
+                in_values,
+                &self.in_ranks,
+                &counts.receive_counts,
+                &counts.receive_displacements,
+                &self.forward_comm,
+            ),
         }
     }
 
@@ -342,22 +518,7 @@ impl<I: Default + Copy + Equivalence> GhostCommunicator<I> {
     ///
     /// This back propagates updated ghost values from the receiver to the original owning process.
     pub fn backward_send_values<T: Equivalence>(&self, out_values: &[T], in_values: &mut [T]) {
-        assert_eq!(out_values.len(), self.total_receive_count);
-        assert_eq!(in_values.len(), self.total_send_count);
-
-        unsafe {
-            mpi_sys::MPI_Neighbor_alltoallv(
-                out_values.as_ptr() as *const c_void,
-                self.receive_counts.as_ptr(),
-                self.receive_displacements.as_ptr(),
-                <I as Equivalence>::equivalent_datatype().as_raw(),
-                in_values.as_mut_ptr() as *mut c_void,
-                self.send_counts.as_ptr(),
-                self.send_displacements.as_ptr(),
-                <I as Equivalence>::equivalent_datatype().as_raw(),
-                self.backward_comm.as_raw(),
-            );
-        }
+        self.backward_send_values_by_chunks(out_values, in_values, 1);
     }
 
     /// Backward send values.
@@ -371,42 +532,824 @@ impl<I: Default + Copy + Equivalence> GhostCommunicator<I> {
         in_values: &mut [T],
         chunk_size: usize,
     ) {
+        self.backward_send_values_with_counts(
+            out_values,
+            in_values,
+            &mut self.chunked_counts(chunk_size),
+        )
+        .unwrap_or_else(|error| panic!("{}", error));
+    }
+
+    /// Backward send values with precomputed counts.
+    ///
+    /// Does the same exchange as [GhostCommunicator::backward_send_values_by_chunks] with the
+    /// chunk size of `counts`, but does not allocate. Returns [Error::Mpi] if MPI reports an
+    /// error.
+    pub(crate) fn backward_send_values_with_counts<T: Equivalence>(
+        &self,
+        out_values: &[T],
+        in_values: &mut [T],
+        counts: &mut ChunkedCounts,
+    ) -> Result<(), Error> {
+        assert_eq!(
+            out_values.len(),
+            self.total_receive_count * counts.chunk_size
+        );
+        assert_eq!(in_values.len(), self.total_send_count * counts.chunk_size);
+
+        match self.backend {
+            GhostCommunicatorBackend::NeighbourhoodCollective => neighbor_alltoallv(
+                out_values,
+                &counts.receive_counts,
+                &counts.receive_displacements,
+                in_values,
+                &counts.send_counts,
+                &counts.send_displacements,
+                &self.backward_comm,
+            ),
+            GhostCommunicatorBackend::PointToPoint => blocking_point_to_point_exchange(
+                &mut counts.requests,
+                out_values,
+                &self.in_ranks,
+                &counts.receive_counts,
+                &counts.receive_displacements,
+                in_values,
+                &self.out_ranks,
+                &counts.send_counts,
+                &counts.send_displacements,
+                &self.backward_comm,
+            ),
+        }
+    }
+
+    /// Scale the counts and displacements of the exchanges by a chunk size.
+    pub(crate) fn chunked_counts(&self, chunk_size: usize) -> ChunkedCounts {
+        ChunkedCounts {
+            chunk_size,
+            send_counts: chunked(&self.send_counts, chunk_size),
+            send_displacements: chunked(&self.send_displacements, chunk_size),
+            receive_counts: chunked(&self.receive_counts, chunk_size),
+            receive_displacements: chunked(&self.receive_displacements, chunk_size),
+            requests: Vec::with_capacity(self.in_ranks.len() + self.out_ranks.len()),
+        }
+    }
+
+    /// Forward send values with a variable number of values per index.
+    ///
+    /// `send_lengths[i]` is the number of values sent for the `i`-th send index and
+    /// `receive_lengths[i]` the number of values received for the `i`-th receive index. The
+    /// values of all indices are stored consecutively in `out_values` and `in_values`.
+    pub fn forward_send_ragged_values<T: Equivalence>(
+        &self,
+        out_values: &[T],
+        send_lengths: &[usize],
+        in_values: &mut [T],
+        receive_lengths: &[usize],
+    ) {
+        assert_eq!(send_lengths.len(), self.total_send_count);
+        assert_eq!(receive_lengths.len(), self.total_receive_count);
+        assert_eq!(out_values.len(), send_lengths.iter().sum::<usize>());
+        assert_eq!(in_values.len(), receive_lengths.iter().sum::<usize>());
+
+        let send_counts = ragged_counts(&self.send_counts, send_lengths);
+        let send_displacements = displacements(&send_counts);
+        let receive_counts = ragged_counts(&self.receive_counts, receive_lengths);
+        let receive_displacements = displacements(&receive_counts);
+
+        match self.backend {
+            GhostCommunicatorBackend::NeighbourhoodCollective => neighbor_alltoallv(
+                out_values,
+                &send_counts,
+                &send_displacements,
+                in_values,
+                &receive_counts,
+                &receive_displacements,
+                &self.forward_comm,
+            ),
+            GhostCommunicatorBackend::PointToPoint => blocking_point_to_point_exchange(
+                &mut Vec::new(),
+                out_values,
+                &self.out_ranks,
+                &send_counts,
+                &send_displacements,
+                in_values,
+                &self.in_ranks,
+                &receive_counts,
+                &receive_displacements,
+                &self.forward_comm,
+            ),
+        }
+        .unwrap_or_else(|error| panic!("{}", error));
+    }
+
+    /// Backward send values with a variable number of values per index.
+    ///
+    /// `receive_lengths[i]` is the number of values sent back for the `i`-th receive index and
+    /// `send_lengths[i]` the number of values received for the `i`-th send index. The values
+    /// of all indices are stored consecutively in `out_values` and `in_values`.
+    pub fn backward_send_ragged_values<T: Equivalence>(
+        &self,
+        out_values: &[T],
+        receive_lengths: &[usize],
+        in_values: &mut [T],
+        send_lengths: &[usize],
+    ) {
+        assert_eq!(receive_lengths.len(), self.total_receive_count);
+        assert_eq!(send_lengths.len(), self.total_send_count);
+        assert_eq!(out_values.len(), receive_lengths.iter().sum::<usize>());
+        assert_eq!(in_values.len(), send_lengths.iter().sum::<usize>());
+
+        let send_counts = ragged_counts(&self.send_counts, send_lengths);
+        let send_displacements = displacements(&send_counts);
+        let receive_counts = ragged_counts(&self.receive_counts, receive_lengths);
+        let receive_displacements = displacements(&receive_counts);
+
+        match self.backend {
+            GhostCommunicatorBackend::NeighbourhoodCollective => neighbor_alltoallv(
+                out_values,
+                &receive_counts,
+                &receive_displacements,
+                in_values,
+                &send_counts,
+                &send_displacements,
+                &self.backward_comm,
+            ),
+            GhostCommunicatorBackend::PointToPoint => blocking_point_to_point_exchange(
+                &mut Vec::new(),
+                out_values,
+                &self.in_ranks,
+                &receive_counts,
+                &receive_displacements,
+                in_values,
+                &self.out_ranks,
+                &send_counts,
+                &send_displacements,
+                &self.backward_comm,
+            ),
+        }
+        .unwrap_or_else(|error| panic!("{}", error));
+    }
+
+    /// Accumulate ghost values onto their owning processes.
+    ///
+    /// The ghost values are sent back to their owners, as in [GhostCommunicator::backward_send_values_by_chunks].
+    /// Each received chunk is then combined with `op` into the chunk of `owned_values` at position
+    /// `owned_position(index)`, where `index` is the corresponding send index. If several processes
+    /// hold a ghost of the same index, all their contributions are combined into the owned value.
+    /// For ghost communicators created with [GhostCommunicator::from_layout] the positions are
+    /// known, see [GhostCommunicator::backward_accumulate].
+    pub fn backward_accumulate_with<T, Op, F>(
+        &self,
+        op: Op,
+        ghost_values: &[T],
+        owned_values: &mut [T],
+        chunk_size: usize,
+        owned_position: F,
+    ) where
+        T: Equivalence + Copy + Default,
+        Op: ReductionOp<T>,
+        F: Fn(I) -> usize,
+    {
+        let mut contributions = vec![T::default(); self.total_send_count * chunk_size];
+        self.backward_send_values_by_chunks(ghost_values, &mut contributions, chunk_size);
+
+        for (&index, chunk) in izip!(self.send_indices.iter(), contributions.chunks(chunk_size)) {
+            let start = owned_position(index) * chunk_size;
+            combine_slices(&op, &mut owned_values[start..start + chunk_size], chunk);
+        }
+    }
+
+    /// Non-blocking forward send values.
+    ///
+    /// Starts the same exchange as [GhostCommunicator::forward_send_values] and returns
+    /// immediately. The buffers remain borrowed until the returned request has completed. The
+    /// request is registered with `scope`, see [ExchangeRequest].
+    pub fn forward_send_values_nonblocking<'b, T: Equivalence, S: Scope<'b>>(
+        &'b self,
+        scope: S,
+        out_values: &'b [T],
+        in_values: &'b mut [T],
+    ) -> ExchangeRequest<'b, T, S> {
+        self.forward_send_values_by_chunks_nonblocking(scope, out_values, in_values, 1)
+    }
+
+    /// Non-blocking forward send values with a given chunk size.
+    ///
+    /// Starts the same exchange as [GhostCommunicator::forward_send_values_by_chunks] and returns
+    /// immediately. The buffers remain borrowed until the returned request has completed. The
+    /// request is registered with `scope`, see [ExchangeRequest].
+    pub fn forward_send_values_by_chunks_nonblocking<'b, T: Equivalence, S: Scope<'b>>(
+        &'b self,
+        scope: S,
+        out_values: &'b [T],
+        in_values: &'b mut [T],
+        chunk_size: usize,
+    ) -> ExchangeRequest<'b, T, S> {
+        assert_eq!(in_values.len(), self.total_receive_count * chunk_size);
+        assert_eq!(out_values.len(), self.total_send_count * chunk_size);
+
+        match self.backend {
+            GhostCommunicatorBackend::NeighbourhoodCollective => ineighbor_alltoallv(
+                scope,
+                out_values,
+                chunked(&self.send_counts, chunk_size),
+                chunked(&self.send_displacements, chunk_size),
+                in_values,
+                chunked(&self.receive_counts, chunk_size),
+                chunked(&self.receive_displacements, chunk_size),
+                &self.forward_comm,
+            ),
+            GhostCommunicatorBackend::PointToPoint => point_to_point_exchange(
+                scope,
+                out_values,
+                &self.out_ranks,
+                &chunked(&self.send_counts, chunk_size),
+                &chunked(&self.send_displacements, chunk_size),
+                in_values,
+                &self.in_ranks,
+                &chunked(&self.receive_counts, chunk_size),
+                &chunked(&self.receive_displacements, chunk_size),
+                &self.forward_comm,
+            ),
+        }
+    }
+
+    /// Non-blocking backward send values.
+    ///
+    /// Starts the same exchange as [GhostCommunicator::backward_send_values] and returns
+    /// immediately. The buffers remain borrowed until the returned request has completed. The
+    /// request is registered with `scope`, see [ExchangeRequest].
+    pub fn backward_send_values_nonblocking<'b, T: Equivalence, S: Scope<'b>>(
+        &'b self,
+        scope: S,
+        out_values: &'b [T],
+        in_values: &'b mut [T],
+    ) -> ExchangeRequest<'b, T, S> {
+        self.backward_send_values_by_chunks_nonblocking(scope, out_values, in_values, 1)
+    }
+
+    /// Non-blocking backward send values with a given chunk size.
+    ///
+    /// Starts the same exchange as [GhostCommunicator::backward_send_values_by_chunks] and returns
+    /// immediately. The buffers remain borrowed until the returned request has completed. The
+    /// request is registered with `scope`, see [ExchangeRequest].
+    pub fn backward_send_values_by_chunks_nonblocking<'b, T: Equivalence, S: Scope<'b>>(
+        &'b self,
+        scope: S,
+        out_values: &'b [T],
+        in_values: &'b mut [T],
+        chunk_size: usize,
+    ) -> ExchangeRequest<'b, T, S> {
         assert_eq!(out_values.len(), self.total_receive_count * chunk_size);
         assert_eq!(in_values.len(), self.total_send_count * chunk_size);
 
-        let chunked_send_counts = self
-            .send_counts
-            .iter()
-            .map(|&x| x * chunk_size as i32)
-            .collect::<Vec<_>>();
-        let chunked_receive_counts = self
-            .receive_counts
-            .iter()
-            .map(|&x| x * chunk_size as i32)
-            .collect::<Vec<_>>();
-        let chunked_send_displacements = self
-            .send_displacements
-            .iter()
-            .map(|&x| x * chunk_size as i32)
-            .collect::<Vec<_>>();
-        let chunked_receive_displacements = self
-            .receive_displacements
+        match self.backend {
+            GhostCommunicatorBackend::NeighbourhoodCollective => ineighbor_alltoallv(
+                scope,
+                out_values,
+                chunked(&self.receive_counts, chunk_size),
+                chunked(&self.receive_displacements, chunk_size),
+                in_values,
+                chunked(&self.send_counts, chunk_size),
+                chunked(&self.send_displacements, chunk_size),
+                &self.backward_comm,
+            ),
+            GhostCommunicatorBackend::PointToPoint => point_to_point_exchange(
+                scope,
+                out_values,
+                &self.in_ranks,
+                &chunked(&self.receive_counts, chunk_size),
+                &chunked(&self.receive_displacements, chunk_size),
+                in_values,
+                &self.out_ranks,
+                &chunked(&self.send_counts, chunk_size),
+                &chunked(&self.send_displacements, chunk_size),
+                &self.backward_comm,
+            ),
+        }
+    }
+}
+
+impl<'a, C: Communicator> GhostCommunicator<'a, usize, C> {
+    /// Create a new ghost communicator for the global indices of an index layout.
+    ///
+    /// The owning ranks of the `ghost_indices` are taken from the `index_layout`, which can be
+    /// any [Layout]. Duplicate
+    /// ghost indices are removed. If `drop_owned` is true, indices owned by the current process
+    /// are ignored. Otherwise, such indices lead to a panic on all processes. See
+    /// [GhostCommunicator::try_from_layout] for a non-panicking version.
+    ///
+    /// In addition to [GhostCommunicator::new] the returned communicator keeps a map from
+    /// the ghost indices to their receive positions, see [GhostCommunicator::receive_position].
+    pub fn from_layout<L: Layout<'a, Comm = C>>(
+        index_layout: &L,
+        ghost_indices: &[usize],
+        drop_owned: bool,
+    ) -> Self {
+        Self::from_layout_with_options(
+            index_layout,
+            ghost_indices,
+            drop_owned,
+            GhostCommunicatorOptions::default(),
+        )
+    }
+
+    /// Create a new ghost communicator for the global indices of an index layout with the
+    /// given options.
+    ///
+    /// See [GhostCommunicator::from_layout] for a description of the arguments.
+    pub fn from_layout_with_options<L: Layout<'a, Comm = C>>(
+        index_layout: &L,
+        ghost_indices: &[usize],
+        drop_owned: bool,
+        options: GhostCommunicatorOptions,
+    ) -> Self {
+        Self::try_from_layout_with_options(index_layout, ghost_indices, drop_owned, options)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Create a new ghost communicator for the global indices of an index layout.
+    ///
+    /// Returns [Error::IndexOutOfRange] or [Error::UnknownOwner] if a ghost index has no owner
+    /// and [Error::OwnedGhostIndex] if `drop_owned` is false and a ghost index is owned by the
+    /// current process. The processes agree on these checks, so that all processes return
+    /// [Error::FailedOnOtherRanks] if they fail on another process. Returns [Error::Mpi] if MPI
+    /// reports an error. See [GhostCommunicator::from_layout] for a description of the
+    /// arguments.
+    pub fn try_from_layout<L: Layout<'a, Comm = C>>(
+        index_layout: &L,
+        ghost_indices: &[usize],
+        drop_owned: bool,
+    ) -> Result<Self, Error> {
+        Self::try_from_layout_with_options(
+            index_layout,
+            ghost_indices,
+            drop_owned,
+            GhostCommunicatorOptions::default(),
+        )
+    }
+
+    /// Create a new ghost communicator for the global indices of an index layout with the
+    /// given options.
+    ///
+    /// See [GhostCommunicator::try_from_layout] for the errors and
+    /// [GhostCommunicator::from_layout] for a description of the arguments.
+    pub fn try_from_layout_with_options<L: Layout<'a, Comm = C>>(
+        index_layout: &L,
+        ghost_indices: &[usize],
+        drop_owned: bool,
+        options: GhostCommunicatorOptions,
+    ) -> Result<Self, Error> {
+        let rank = index_layout.comm().rank() as usize;
+
+        let ghost_indices = ghost_indices.iter().copied().unique().collect::<Vec<_>>();
+
+        // An index without an owner is reported like an owned ghost index, so that the
+        // processes agree on the error before the setup continues.
+
+        let (ranks, mut result) = match index_layout.try_ranks_from_indices(&ghost_indices) {
+            Ok(ranks) => (ranks, Ok(())),
+            Err(error) => (Vec::new(), Err(error)),
+        };
+
+        let mut unique_ghost_indices = Vec::<usize>::new();
+        let mut owning_ranks = Vec::<usize>::new();
+
+        for (index, owner) in izip!(ghost_indices, ranks) {
+            if owner != rank {
+                unique_ghost_indices.push(index);
+                owning_ranks.push(owner);
+            } else if !drop_owned && result.is_ok() {
+                result = Err(Error::OwnedGhostIndex { index });
+            }
+        }
+
+        agree_collectively(index_layout.comm(), result)?;
+
+        let mut ghost_communicator = Self::try_new_with_options(
+            &unique_ghost_indices,
+            &owning_ranks,
+            index_layout.comm_ref().clone(),
+            options,
+        )?;
+
+        ghost_communicator.receive_positions = ghost_communicator
+            .receive_indices
             .iter()
-            .map(|&x| x * chunk_size as i32)
+            .enumerate()
+            .map(|(position, &index)| (index, position))
+            .collect();
+
+        ghost_communicator.send_positions = Some(
+            ghost_communicator
+                .send_indices
+                .iter()
+                .map(|&index| index_layout.global2local_owned(index).unwrap())
+                .collect(),
+        );
+
+        Ok(ghost_communicator)
+    }
+
+    /// Accumulate ghost values onto their owning processes.
+    ///
+    /// `owned_values` holds `chunk_size` values for each local index of the index layout from
+    /// which the ghost communicator was created. Each ghost value is combined with `op` into the
+    /// owned value of its index, as in [GhostCommunicator::backward_accumulate_with].
+    ///
+    /// Panics if the ghost communicator was not created with [GhostCommunicator::from_layout].
+    pub fn backward_accumulate<T, Op>(
+        &self,
+        op: Op,
+        ghost_values: &[T],
+        owned_values: &mut [T],
+        chunk_size: usize,
+    ) where
+        T: Equivalence + Copy + Default,
+        Op: ReductionOp<T>,
+    {
+        let send_positions = self.send_positions.as_ref().expect(
+            "The owned positions are only known for ghost communicators created from a layout.",
+        );
+
+        let mut contributions = vec![T::default(); self.total_send_count * chunk_size];
+        self.backward_send_values_by_chunks(ghost_values, &mut contributions, chunk_size);
+
+        for (&position, chunk) in izip!(send_positions, contributions.chunks(chunk_size)) {
+            let start = position * chunk_size;
+            combine_slices(&op, &mut owned_values[start..start + chunk_size], chunk);
+        }
+    }
+}
+
+impl<I: Default + Copy + Equivalence + Hash + Eq, C: Communicator> GhostCommunicator<'_, I, C> {
+    /// Return the position of a ghost index in the receive indices.
+    ///
+    /// Returns `None` if `index` is not received on the current process. The map from ghost
+    /// indices to receive positions is only available for ghost communicators created with
+    /// [GhostCommunicator::from_layout]. For all others `None` is returned.
+    pub fn receive_position(&self, index: I) -> Option<usize> {
+        self.receive_positions.get(&index).copied()
+    }
+}
+
+/// Create a distributed graph communicator with the given in and out neighbours.
+///
+/// The neighbours are given as ranks together with optional edge weights. If `reorder` is
+/// true, MPI may assign new ranks to the processes.
+fn create_graph_comm<C: Communicator>(
+    comm: &C,
+    (sources, source_weights): (&[i32], Option<&[i32]>),
+    (destinations, destination_weights): (&[i32], Option<&[i32]>),
+    reorder: bool,
+) -> Result<SimpleCommunicator, Error> {
+    // To create the actual communicator need to call into mpi-sys as not yet wrapped into
+    // higher level interface.
+
+    unsafe {
+        let weights = |weights: Option<&[i32]>| {
+            weights.map_or(mpi_sys::RSMPI_UNWEIGHTED() as *const i32, |weights| {
+                weights.as_ptr()
+            })
+        };
+
+        let mut raw_comm = mpi_sys::RSMPI_COMM_NULL;
+        check_mpi(mpi_sys::MPI_Dist_graph_create_adjacent(
+            comm.as_raw(),
+            sources.len() as i32,
+            sources.as_ptr(),
+            weights(source_weights),
+            destinations.len() as i32,
+            destinations.as_ptr(),
+            weights(destination_weights),
+            mpi_sys::RSMPI_INFO_NULL,
+            reorder as i32,
+            &mut raw_comm,
+        ))?;
+
+        Ok(SimpleCommunicator::from_raw(raw_comm))
+    }
+}
+
+/// Scale counts or displacements by a chunk size.
+pub(crate) fn chunked(values: &[i32], chunk_size: usize) -> Vec<i32> {
+    values.iter().map(|&x| x * chunk_size as i32).collect()
+}
+
+/// Counts and displacements of the exchanges of a ghost communicator for a fixed chunk size.
+///
+/// Exchanges that are repeated with the same chunk size keep these instead of scaling the
+/// counts again for every exchange. The request buffer is used by the point-to-point backend.
+pub(crate) struct ChunkedCounts {
+    chunk_size: usize,
+    send_counts: Vec<i32>,
+    send_displacements: Vec<i32>,
+    receive_counts: Vec<i32>,
+    receive_displacements: Vec<i32>,
+    requests: Vec<mpi_sys::MPI_Request>,
+}
+
+impl ChunkedCounts {
+    /// The chunk size of the counts.
+    pub(crate) fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+}
+
+/// Sum up the lengths of the indices exchanged with each neighbour.
+///
+/// `counts` are the numbers of indices exchanged with the neighbours and `lengths` the
+/// numbers of values of these indices.
+fn ragged_counts(counts: &[i32], lengths: &[usize]) -> Vec<i32> {
+    let mut start = 0;
+    counts
+        .iter()
+        .map(|&count| {
+            let end = start + count as usize;
+            let total = lengths[start..end].iter().sum::<usize>() as i32;
+            start = end;
+            total
+        })
+        .collect()
+}
+
+/// Blocking neighbourhood all-to-all exchange.
+fn neighbor_alltoallv<T: Equivalence>(
+    out_values: &[T],
+    send_counts: &[i32],
+    send_displacements: &[i32],
+    in_values: &mut [T],
+    receive_counts: &[i32],
+    receive_displacements: &[i32],
+    comm: &SimpleCommunicator,
+) -> Result<(), Error> {
+    check_mpi(unsafe {
+        mpi_sys::MPI_Neighbor_alltoallv(
+            out_values.as_ptr() as *const c_void,
+            send_counts.as_ptr(),
+            send_displacements.as_ptr(),
+            <T as Equivalence>::equivalent_datatype().as_raw(),
+            in_values.as_mut_ptr() as *mut c_void,
+            receive_counts.as_ptr(),
+            receive_displacements.as_ptr(),
+            <T as Equivalence>::equivalent_datatype().as_raw(),
+            comm.as_raw(),
+        )
+    })
+}
+
+/// Send counts to target ranks with a dense all-to-all.
+///
+/// Returns the ranks that sent a nonzero count to the current process, in ascending order,
+/// together with the counts.
+fn dense_exchange_counts<C: Communicator>(
+    target_ranks: &[i32],
+    counts: &[i32],
+    comm: &C,
+) -> (Vec<i32>, Vec<i32>) {
+    let mut all_counts = vec![0; comm.size() as usize];
+    for (&rank, &count) in izip!(target_ranks, counts) {
+        all_counts[rank as usize] = count;
+    }
+
+    let mut received_counts = vec![0; comm.size() as usize];
+    comm.all_to_all_into(&all_counts, &mut received_counts);
+
+    received_counts
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count != 0)
+        .map(|(rank, &count)| (rank as i32, count))
+        .unzip()
+}
+
+/// Send counts to target ranks with the non-blocking consensus algorithm.
+///
+/// Each count is sent with a synchronous send. A synchronous send completes once it has been
+/// matched, so once all sends of a process have completed it enters a non-blocking barrier.
+/// Incoming counts are received until the barrier has completed on all processes.
+///
+/// Returns the ranks that sent a count to the current process, in ascending order,
+/// together with the counts.
+fn sparse_exchange_counts<C: Communicator>(
+    target_ranks: &[i32],
+    counts: &[i32],
+    comm: &C,
+) -> (Vec<i32>, Vec<i32>) {
+    // The messages go over a duplicate so that they cannot be matched by other messages
+    // on the parent communicator.
+    let comm = comm.duplicate();
+    let mut received = Vec::<(i32, i32)>::new();
+
+    mpi::request::scope(|scope| {
+        let mut send_requests = izip!(target_ranks, counts)
+            .map(|(&rank, count)| {
+                comm.process_at_rank(rank)
+                    .immediate_synchronous_send(scope, count)
+            })
             .collect::<Vec<_>>();
+        let mut barrier = None;
+
+        loop {
+            if let Some((message, status)) = comm.any_process().immediate_matched_probe() {
+                let (count, _) = message.matched_receive::<i32>();
+                received.push((status.source_rank(), count));
+            }
 
-        unsafe {
-            mpi_sys::MPI_Neighbor_alltoallv(
-                out_values.as_ptr() as *const c_void,
-                chunked_receive_counts.as_ptr(),
-                chunked_receive_displacements.as_ptr(),
-                <I as Equivalence>::equivalent_datatype().as_raw(),
-                in_values.as_mut_ptr() as *mut c_void,
-                chunked_send_counts.as_ptr(),
-                chunked_send_displacements.as_ptr(),
-                <I as Equivalence>::equivalent_datatype().as_raw(),
-                self.backward_comm.as_raw(),
-            );
+            match barrier.take() {
+                None => {
+                    send_requests = send_requests
+                        .into_iter()
+                        .filter_map(|request| request.test().err())
+                        .collect();
+                    if send_requests.is_empty() {
+                        barrier = Some(comm.immediate_barrier());
+                    }
+                }
+                Some(request) => match request.test() {
+                    Ok(_) => break,
+                    Err(request) => barrier = Some(request),
+                },
+            }
         }
+    });
+
+    received.sort_unstable();
+    received.into_iter().unzip()
+}
+
+/// Start a non-blocking neighbourhood all-to-all exchange.
+#[allow(clippy::too_many_arguments)]
+fn ineighbor_alltoallv<'a, T: Equivalence, S: Scope<'a>>(
+    scope: S,
+    out_values: &'a [T],
+    send_counts: Vec<i32>,
+    send_displacements: Vec<i32>,
+    in_values: &'a mut [T],
+    receive_counts: Vec<i32>,
+    receive_displacements: Vec<i32>,
+    comm: &'a SimpleCommunicator,
+) -> ExchangeRequest<'a, T, S> {
+    unsafe {
+        let mut request = mpi_sys::RSMPI_REQUEST_NULL;
+        mpi_sys::MPI_Ineighbor_alltoallv(
+            out_values.as_ptr() as *const c_void,
+            send_counts.as_ptr(),
+            send_displacements.as_ptr(),
+            <T as Equivalence>::equivalent_datatype().as_raw(),
+            in_values.as_mut_ptr() as *mut c_void,
+            receive_counts.as_ptr(),
+            receive_displacements.as_ptr(),
+            <T as Equivalence>::equivalent_datatype().as_raw(),
+            comm.as_raw(),
+            &mut request,
+        );
+        // The count vectors are moved into the request. Moving a vector does not move
+        // its heap allocation, so the pointers passed to MPI stay valid.
+        ExchangeRequest::from_raw(
+            vec![request],
+            vec![
+                send_counts,
+                send_displacements,
+                receive_counts,
+                receive_displacements,
+            ],
+            scope,
+        )
+    }
+}
+
+/// Start a non-blocking exchange with point-to-point messages.
+///
+/// A message is received from each of the `source_ranks` and sent to each of the
+/// `target_ranks`. Counts and displacements are given per neighbour.
+#[allow(clippy::too_many_arguments)]
+fn point_to_point_exchange<'a, T: Equivalence, S: Scope<'a>>(
+    scope: S,
+    out_values: &'a [T],
+    target_ranks: &[i32],
+    send_counts: &[i32],
+    send_displacements: &[i32],
+    in_values: &'a mut [T],
+    source_ranks: &[i32],
+    receive_counts: &[i32],
+    receive_displacements: &[i32],
+    comm: &'a SimpleCommunicator,
+) -> ExchangeRequest<'a, T, S> {
+    let mut requests = Vec::with_capacity(source_ranks.len() + target_ranks.len());
+
+    // Errors of non-blocking exchanges are not reported. Messages that could not be posted
+    // have null requests, which complete immediately.
+    unsafe {
+        let _ = post_point_to_point(
+            &mut requests,
+            out_values,
+            target_ranks,
+            send_counts,
+            send_displacements,
+            in_values,
+            source_ranks,
+            receive_counts,
+            receive_displacements,
+            comm,
+        );
+        ExchangeRequest::from_raw(requests, Vec::new(), scope)
+    }
+}
+
+/// Blocking exchange with point-to-point messages.
+///
+/// Does the same exchange as [point_to_point_exchange] and waits for it to complete. The
+/// requests are stored in `requests`, which is cleared first. If posting a message fails, the
+/// messages that were posted are still completed before the error is returned.
+#[allow(clippy::too_many_arguments)]
+fn blocking_point_to_point_exchange<T: Equivalence>(
+    requests: &mut Vec<mpi_sys::MPI_Request>,
+    out_values: &[T],
+    target_ranks: &[i32],
+    send_counts: &[i32],
+    send_displacements: &[i32],
+    in_values: &mut [T],
+    source_ranks: &[i32],
+    receive_counts: &[i32],
+    receive_displacements: &[i32],
+    comm: &SimpleCommunicator,
+) -> Result<(), Error> {
+    requests.clear();
+
+    unsafe {
+        let posted = post_point_to_point(
+            requests,
+            out_values,
+            target_ranks,
+            send_counts,
+            send_displacements,
+            in_values,
+            source_ranks,
+            receive_counts,
+            receive_displacements,
+            comm,
+        );
+        let completed = check_mpi(mpi_sys::MPI_Waitall(
+            requests.len() as i32,
+            requests.as_mut_ptr(),
+            mpi_sys::RSMPI_STATUSES_IGNORE,
+        ));
+        posted.and(completed)
+    }
+}
+
+/// Post the receives and sends of a point-to-point exchange and append their requests.
+///
+/// The buffers must not be accessed until the requests have completed. All messages are
+/// posted even if MPI reports an error for one of them. The first error is returned.
+#[allow(clippy::too_many_arguments)]
+unsafe fn post_point_to_point<T: Equivalence>(
+    requests: &mut Vec<mpi_sys::MPI_Request>,
+    out_values: &[T],
+    target_ranks: &[i32],
+    send_counts: &[i32],
+    send_displacements: &[i32],
+    in_values: &mut [T],
+    source_ranks: &[i32],
+    receive_counts: &[i32],
+    receive_displacements: &[i32],
+    comm: &SimpleCommunicator,
+) -> Result<(), Error> {
+    let mut result = Ok(());
+
+    // The receives are posted first so that incoming messages can be matched directly.
+    for (&source, &count, &displacement) in
+        izip!(source_ranks, receive_counts, receive_displacements)
+    {
+        let mut request = mpi_sys::RSMPI_REQUEST_NULL;
+        let code = mpi_sys::MPI_Irecv(
+            in_values.as_mut_ptr().add(displacement as usize) as *mut c_void,
+            count,
+            <T as Equivalence>::equivalent_datatype().as_raw(),
+            source,
+            0,
+            comm.as_raw(),
+            &mut request,
+        );
+        result = result.and(check_mpi(code));
+        requests.push(request);
+    }
+
+    for (&target, &count, &displacement) in izip!(target_ranks, send_counts, send_displacements) {
+        let mut request = mpi_sys::RSMPI_REQUEST_NULL;
+        let code = mpi_sys::MPI_Isend(
+            out_values.as_ptr().add(displacement as usize) as *const c_void,
+            count,
+            <T as Equivalence>::equivalent_datatype().as_raw(),
+            target,
+            0,
+            comm.as_raw(),
+            &mut request,
+        );
+        result = result.and(check_mpi(code));
+        requests.push(request);
     }
+
+    result
 }
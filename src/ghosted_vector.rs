@@ -0,0 +1,177 @@
+//! Distributed vectors with ghost entries.
+//!
+//! A [GhostedVector] stores the entries owned by a process according to a [Layout]
+//! followed by copies of ghost entries owned by other processes. Ghosts are updated from
+//! their owners with [GhostedVector::update_ghosts]. Contributions stored in the ghosts are
+//! combined back into the owned entries with [GhostedVector::accumulate_ghosts].
+
+use std::rc::Rc;
+
+use mpi::traits::{Communicator, Equivalence};
+
+use crate::layout::Layout;
+use crate::reduction::ReductionOp;
+use crate::{GhostCommunicator, IndexLayout};
+
+/// A distributed vector with owned and ghost entries.
+///
+/// Each entry consists of `chunk_size` values. The entries are stored contiguously,
+/// the owned entries in the order of the index layout, followed by the ghost entries. The index
+/// layout can be any [Layout] and defaults to an [IndexLayout].
+pub struct GhostedVector<'a, T, C: Communicator, L: Layout<'a, Comm = C> = IndexLayout<'a, C>> {
+    index_layout: Rc<L>,
+    ghost_communicator: GhostCommunicator<'a, usize, C>,
+    chunk_size: usize,
+    data: Vec<T>,
+}
+
+impl<'a, T: Equivalence + Copy + Default, C: Communicator, L: Layout<'a, Comm = C>>
+    GhostedVector<'a, T, C, L>
+{
+    /// Create a new ghosted vector.
+    ///
+    /// The `ghost_indices` are the global indices of the ghost entries required on the current
+    /// process. Duplicates and indices owned by the current process are ignored. All values are
+    /// initialised with the default value of `T`.
+    pub fn new(index_layout: Rc<L>, ghost_indices: &[usize], chunk_size: usize) -> Self {
+        // The ghosts are stored in the order in which they are received.
+
+        let ghost_communicator =
+            GhostCommunicator::from_layout(index_layout.as_ref(), ghost_indices, true);
+
+        let number_of_entries =
+            index_layout.number_of_local_indices() + ghost_communicator.total_receive_count();
+
+        Self {
+            index_layout,
+            ghost_communicator,
+            chunk_size,
+            data: vec![T::default(); number_of_entries * chunk_size],
+        }
+    }
+
+    /// Update the ghost entries with the values from their owning processes.
+    pub fn update_ghosts(&mut self) {
+        let chunk_size = self.chunk_size;
+        let number_of_owned = self.number_of_owned();
+        let (owned, ghosts) = self.data.split_at_mut(number_of_owned * chunk_size);
+
+        let mut send_values =
+            Vec::<T>::with_capacity(self.ghost_communicator.total_send_count() * chunk_size);
+        for &index in self.ghost_communicator.send_indices() {
+            let start = self.index_layout.global2local_owned(index).unwrap() * chunk_size;
+            send_values.extend_from_slice(&owned[start..start + chunk_size]);
+        }
+
+        self.ghost_communicator
+            .forward_send_values_by_chunks(&send_values, ghosts, chunk_size);
+    }
+
+    /// Combine the ghost entries into the owned entries on their owning processes.
+    ///
+    /// If several processes hold a ghost of the same entry all contributions are combined
+    /// with `op`. The ghost entries themselves are not modified.
+    pub fn accumulate_ghosts<Op: ReductionOp<T>>(&mut self, op: Op) {
+        let chunk_size = self.chunk_size;
+        let number_of_owned = self.number_of_owned();
+        let (owned, ghosts) = self.data.split_at_mut(number_of_owned * chunk_size);
+
+        self.ghost_communicator
+            .backward_accumulate(op, ghosts, owned, chunk_size);
+    }
+
+    /// Return the owned values.
+    pub fn owned(&self) -> &[T] {
+        &self.data[..self.number_of_owned() * self.chunk_size]
+    }
+
+    /// Return the owned values as mutable slice.
+    pub fn owned_mut(&mut self) -> &mut [T] {
+        let end = self.number_of_owned() * self.chunk_size;
+        &mut self.data[..end]
+    }
+
+    /// Return the ghost values.
+    pub fn ghosts(&self) -> &[T] {
+        &self.data[self.number_of_owned() * self.chunk_size..]
+    }
+
+    /// Return the ghost values as mutable slice.
+    pub fn ghosts_mut(&mut self) -> &mut [T] {
+        let start = self.number_of_owned() * self.chunk_size;
+        &mut self.data[start..]
+    }
+
+    /// Return all values, the owned values followed by the ghost values.
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+
+    /// Return the global indices of the ghost entries in storage order.
+    pub fn ghost_indices(&self) -> &[usize] {
+        self.ghost_communicator.receive_indices()
+    }
+
+    /// Return the position of a global index in the storage of the vector.
+    ///
+    /// Returns `None` if the index is neither owned nor a ghost on the current process.
+    pub fn local_position(&self, global_index: usize) -> Option<usize> {
+        if let Some(local_index) = self.index_layout.global2local_owned(global_index) {
+            Some(local_index)
+        } else {
+            self.ghost_communicator
+                .receive_position(global_index)
+                .map(|position| self.number_of_owned() + position)
+        }
+    }
+
+    /// Return the values of a global index.
+    ///
+    /// Returns `None` if the index is neither owned nor a ghost on the current process.
+    pub fn get(&self, global_index: usize) -> Option<&[T]> {
+        let start = self.local_position(global_index)? * self.chunk_size;
+        Some(&self.data[start..start + self.chunk_size])
+    }
+
+    /// Return the values of a global index as mutable slice.
+    ///
+    /// Returns `None` if the index is neither owned nor a ghost on the current process.
+    pub fn get_mut(&mut self, global_index: usize) -> Option<&mut [T]> {
+        let start = self.local_position(global_index)? * self.chunk_size;
+        Some(&mut self.data[start..start + self.chunk_size])
+    }
+
+    /// Return the number of owned entries.
+    pub fn number_of_owned(&self) -> usize {
+        self.index_layout.number_of_local_indices()
+    }
+
+    /// Return the number of ghost entries.
+    pub fn number_of_ghosts(&self) -> usize {
+        self.ghost_communicator.total_receive_count()
+    }
+
+    /// Return the chunk size.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Return the index layout.
+    pub fn index_layout(&self) -> Rc<L> {
+        self.index_layout.clone()
+    }
+
+    /// Return the ghost communicator.
+    pub fn ghost_communicator(&self) -> &GhostCommunicator<'a, usize, C> {
+        &self.ghost_communicator
+    }
+}
+
+impl<'a, T, C: Communicator, L: Layout<'a, Comm = C>> GhostedVector<'a, T, C, L> {
+    /// Iterate over the global indices of the entries in storage order.
+    pub fn global_indices(&self) -> impl Iterator<Item = usize> + use<'_, 'a, T, C, L> {
+        (0..self.index_layout.number_of_local_indices())
+            .map(|local_index| self.index_layout.local2global(local_index).unwrap())
+            .chain(self.ghost_communicator.receive_indices().iter().copied())
+    }
+}
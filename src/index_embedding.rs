@@ -8,24 +8,30 @@ use std::{collections::HashMap, rc::Rc};
 use itertools::izip;
 use mpi::traits::Communicator;
 
+use crate::comm_ref::CommRef;
+use crate::error::{check_size, Error};
+use crate::layout::Layout;
 use crate::IndexLayout;
 
 /// Create a new embedded indexing
-pub struct IndexEmbedding<'a, C: Communicator> {
-    global_layout: Rc<IndexLayout<'a, C>>,
+///
+/// The global layout can be any [Layout] and defaults to an [IndexLayout].
+pub struct IndexEmbedding<'a, C: Communicator, L: Layout<'a, Comm = C> = IndexLayout<'a, C>> {
+    global_layout: Rc<L>,
     embedded_index_subset: Vec<usize>,
     embedded_layout: Rc<IndexLayout<'a, C>>,
     local_to_embedded_index: HashMap<usize, usize>,
 }
 
-impl<'a, C: Communicator> IndexEmbedding<'a, C> {
+impl<'a, C: Communicator, L: Layout<'a, Comm = C>> IndexEmbedding<'a, C, L> {
     /// Create a new index embedding.
     ///
-    /// Note. Each index in `embedded_index_subset` must be unique.
+    /// Note. Each index in `embedded_index_subset` must be unique. The communicator can be
+    /// borrowed or shared, see [CommRef].
     pub fn new(
-        global_layout: Rc<IndexLayout<'a, C>>,
+        global_layout: Rc<L>,
         embedded_index_subset: &[usize],
-        comm: &'a C,
+        comm: impl Into<CommRef<'a, C>>,
     ) -> Self {
         // Let us setup an index layout for the subset.
 
@@ -60,7 +66,7 @@ impl<'a, C: Communicator> IndexEmbedding<'a, C> {
     }
 
     /// Return the global layout
-    pub fn global_layout(&self) -> Rc<IndexLayout<'a, C>> {
+    pub fn global_layout(&self) -> Rc<L> {
         self.global_layout.clone()
     }
 
@@ -85,8 +91,7 @@ impl<'a, C: Communicator> IndexEmbedding<'a, C> {
 
     /// Map a global index to the corresponding embedded index
     pub fn global_index_to_embedded_index(&self, global_index: usize) -> Option<usize> {
-        let rank = self.global_layout.comm().rank() as usize;
-        self.local_index_to_embedded_index(self.global_layout.global2local(rank, global_index)?)
+        self.local_index_to_embedded_index(self.global_layout.global2local_owned(global_index)?)
     }
 
     /// Embed a data vector from an embedded indexing to a local indexing.
@@ -95,20 +100,48 @@ impl<'a, C: Communicator> IndexEmbedding<'a, C> {
     /// copied into a vector of length `n * chunk_size` where the data is copied from the embedded indices
     /// to the corresponding local index positions. The values not contained in the embedded indices are set to
     /// the default value of the data type.
+    ///
+    /// Panics if the arguments are invalid. See [IndexEmbedding::try_embed_data] for a
+    /// non-panicking version.
     pub fn embed_data<T: Default + Copy>(
         &self,
         data: &[T],
         out_vector: &mut [T],
         chunk_size: usize,
     ) {
-        for (local_index, chunk) in
+        self.try_embed_data(data, out_vector, chunk_size)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Embed a data vector from an embedded indexing to a local indexing.
+    ///
+    /// Returns [Error::SizeMismatch] if `data` or `out_vector` do not have the required lengths
+    /// and [Error::IndexOutOfRange] if an embedded index is not a local index of the global
+    /// layout. `out_vector` is left untouched in case of an error. No data is exchanged with
+    /// other processes, so an error only affects the current process.
+    pub fn try_embed_data<T: Default + Copy>(
+        &self,
+        data: &[T],
+        out_vector: &mut [T],
+        chunk_size: usize,
+    ) -> Result<(), Error> {
+        let number_of_local_indices = self.global_layout.number_of_local_indices();
+
+        check_size(chunk_size * self.embedded_index_subset.len(), data.len())?;
+        check_size(chunk_size * number_of_local_indices, out_vector.len())?;
+        self.check_embedded_indices()?;
+
+        out_vector.fill(T::default());
+
+        for (&local_index, chunk) in
             izip!(self.embedded_index_subset.iter(), data.chunks(chunk_size))
         {
-            let local_index = self.local_index_to_embedded_index(*local_index).unwrap();
             let local_start_index = local_index * chunk_size;
             let local_end_index = local_start_index + chunk_size;
             out_vector[local_start_index..local_end_index].copy_from_slice(chunk);
         }
+
+        Ok(())
     }
 
     /// Extract embedded data from a local vector.
@@ -116,11 +149,35 @@ impl<'a, C: Communicator> IndexEmbedding<'a, C> {
     /// Given a vector of length `n * chunk_size` where `n` is the number of local indices, extract the data
     /// associated with the `m` embedded indices. The data is copied into a vector of length `m * chunk_size`
     /// with ordering given by the embedded indices.
+    ///
+    /// Panics if the arguments are invalid. See [IndexEmbedding::try_extract_embedded_data]
+    /// for a non-panicking version.
     pub fn extract_embedded_data<T: Default + Copy>(
         &self,
         data: &[T],
         chunk_size: usize,
     ) -> Vec<T> {
+        self.try_extract_embedded_data(data, chunk_size)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Extract embedded data from a local vector.
+    ///
+    /// Returns [Error::SizeMismatch] if `data` does not have `chunk_size` values for each local
+    /// index and [Error::IndexOutOfRange] if an embedded index is not a local index of the
+    /// global layout. As for [IndexEmbedding::try_embed_data], an error only affects the
+    /// current process.
+    pub fn try_extract_embedded_data<T: Default + Copy>(
+        &self,
+        data: &[T],
+        chunk_size: usize,
+    ) -> Result<Vec<T>, Error> {
+        check_size(
+            chunk_size * self.global_layout.number_of_local_indices(),
+            data.len(),
+        )?;
+        self.check_embedded_indices()?;
+
         let mut extracted_data =
             vec![T::default(); self.embedded_layout.number_of_local_indices() * chunk_size];
 
@@ -131,6 +188,19 @@ impl<'a, C: Communicator> IndexEmbedding<'a, C> {
             chunk.copy_from_slice(&data[local_index * chunk_size..(1 + local_index) * chunk_size]);
         }
 
-        extracted_data
+        Ok(extracted_data)
+    }
+
+    /// Check that all embedded indices are local indices of the global layout.
+    fn check_embedded_indices(&self) -> Result<(), Error> {
+        let bound = self.global_layout.number_of_local_indices();
+        match self
+            .embedded_index_subset
+            .iter()
+            .find(|&&index| index >= bound)
+        {
+            Some(&index) => Err(Error::IndexOutOfRange { index, bound }),
+            None => Ok(()),
+        }
     }
 }
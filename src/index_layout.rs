@@ -3,8 +3,11 @@
 //! An [IndexLayout] specified how degrees of freedom are distributed among processes.
 //! We always assume that a process has a contiguous set of degrees of freedom.
 
-use crate::array_tools::redistribute;
-use itertools::Itertools;
+use crate::comm_ref::CommRef;
+use crate::error::{agree_collectively, check_size, failed_ranks, Error};
+use crate::layout_mover::LayoutMover;
+use crate::layout_remap::LayoutRemap;
+use mpi::collective::SystemOperation;
 use mpi::traits::{Communicator, CommunicatorCollectives, Equivalence};
 
 // An index layout specifying index ranges on each rank.
@@ -13,7 +16,7 @@ use mpi::traits::{Communicator, CommunicatorCollectives, Equivalence};
 /// starting with the first n0 indices on rank 0, the next n1 indices on rank 1, etc.
 pub struct IndexLayout<'a, C: Communicator> {
     counts: Vec<usize>,
-    comm: &'a C,
+    comm: CommRef<'a, C>,
 }
 
 impl<C: Communicator> std::fmt::Debug for IndexLayout<'_, C> {
@@ -31,17 +34,37 @@ impl<C: Communicator> Clone for IndexLayout<'_, C> {
     fn clone(&self) -> Self {
         Self {
             counts: self.counts.clone(),
-            comm: self.comm,
+            comm: self.comm.clone(),
         }
     }
 }
 
+/// A load-balanced index layout, see [IndexLayout::from_local_weights].
+pub struct BalancedLayout<'a, C: Communicator> {
+    /// The balanced layout.
+    pub layout: IndexLayout<'a, C>,
+    /// The remap from the current distribution of the indices to the balanced layout.
+    pub remap: LayoutRemap<'a, C>,
+}
+
+impl<'a, C: Communicator> BalancedLayout<'a, C> {
+    /// Create a balanced layout with the remap from the `current` layout.
+    fn new(current: &IndexLayout<'a, C>, layout: IndexLayout<'a, C>) -> Self {
+        let remap = LayoutRemap::new(current, &layout);
+        Self { layout, remap }
+    }
+}
+
 impl<'a, C: Communicator> IndexLayout<'a, C> {
     /// Create a new index layout.
     ///
-    /// The counts specify the number of indices on each rank.
-    pub fn new(counts: Vec<usize>, comm: &'a C) -> Self {
-        Self { counts, comm }
+    /// The counts specify the number of indices on each rank. The communicator can be
+    /// borrowed or shared, see [CommRef].
+    pub fn new(counts: Vec<usize>, comm: impl Into<CommRef<'a, C>>) -> Self {
+        Self {
+            counts,
+            comm: comm.into(),
+        }
     }
 
     /// Create an index layout with equidistributed chunks.
@@ -51,7 +74,12 @@ impl<'a, C: Communicator> IndexLayout<'a, C> {
     /// `nchunks` is the total number of chunks across all processes.
     /// The total number of indices is therefore `nchunks * chunk_size`.
     /// Chunks are distributed as equally as possible across the processes with the remainder distributed to the first few processes.
-    pub fn from_equidistributed_chunks(nchunks: usize, chunk_size: usize, comm: &'a C) -> Self {
+    pub fn from_equidistributed_chunks(
+        nchunks: usize,
+        chunk_size: usize,
+        comm: impl Into<CommRef<'a, C>>,
+    ) -> Self {
+        let comm = comm.into();
         let nindices = nchunks * chunk_size;
         let comm_size = comm.size() as usize;
 
@@ -116,7 +144,11 @@ impl<'a, C: Communicator> IndexLayout<'a, C> {
     }
 
     /// Create an index layout from each process reporting its own number of indices.
-    pub fn from_local_counts(number_of_local_indices: usize, comm: &'a C) -> Self {
+    pub fn from_local_counts(
+        number_of_local_indices: usize,
+        comm: impl Into<CommRef<'a, C>>,
+    ) -> Self {
+        let comm = comm.into();
         let size = comm.size() as usize;
         let mut counts = vec![0; size + 1];
         comm.all_gather_into(&number_of_local_indices, &mut counts[1..]);
@@ -126,6 +158,97 @@ impl<'a, C: Communicator> IndexLayout<'a, C> {
         Self { counts, comm }
     }
 
+    /// Create a load-balanced index layout from weights of the indices.
+    ///
+    /// `weights` are the weights of the local indices in their current order, i.e. the indices
+    /// are currently distributed as in [IndexLayout::from_local_counts] with `weights.len()`
+    /// local indices. The new layout keeps the order of the indices and chooses the index ranges
+    /// such that the sums of the weights on the processes are as even as possible. An index is
+    /// assigned to the process whose share of the total weight contains the midpoint of the
+    /// weight interval of the index. If all weights are zero the indices are distributed by count.
+    ///
+    /// Returns the balanced layout together with a remap that moves data from the current
+    /// distribution to the balanced layout. This is a collective operation. Panics on all
+    /// processes if the weights of any process are negative or NaN.
+    pub fn from_local_weights(
+        weights: &[f64],
+        comm: impl Into<CommRef<'a, C>>,
+    ) -> BalancedLayout<'a, C> {
+        let comm = comm.into();
+
+        // The weights are checked on all processes together, so that all processes panic if
+        // any process has invalid weights.
+
+        let invalid_ranks = failed_ranks(&*comm, !weights.iter().all(|&weight| weight >= 0.0));
+        assert!(
+            invalid_ranks.is_empty(),
+            "Weights must be non-negative. Invalid weights on ranks {:?}.",
+            invalid_ranks
+        );
+
+        let size = comm.size() as usize;
+        let current = Self::from_local_counts(weights.len(), comm.clone());
+
+        let local_weight = weights.iter().sum::<f64>();
+        let mut total_weight = 0.0;
+        comm.all_reduce_into(&local_weight, &mut total_weight, SystemOperation::sum());
+
+        if total_weight == 0.0 {
+            let balanced =
+                Self::from_equidistributed_chunks(current.number_of_global_indices(), 1, comm);
+            return BalancedLayout::new(&current, balanced);
+        }
+
+        // The result of an exclusive scan is undefined on the first rank.
+        let mut offset = 0.0;
+        comm.exclusive_scan_into(&local_weight, &mut offset, SystemOperation::sum());
+        if comm.rank() == 0 {
+            offset = 0.0;
+        }
+
+        let mut local_counts = vec![0; size];
+        for &weight in weights {
+            let midpoint = offset + 0.5 * weight;
+            offset += weight;
+            let rank = (midpoint / total_weight * size as f64) as usize;
+            local_counts[rank.min(size - 1)] += 1;
+        }
+
+        let mut counts = vec![0; size + 1];
+        comm.all_reduce_into(&local_counts[..], &mut counts[1..], SystemOperation::sum());
+        for i in 1..=size {
+            counts[i] += counts[i - 1];
+        }
+
+        BalancedLayout::new(&current, Self { counts, comm })
+    }
+
+    /// Check that the layout is well-formed and identical on all processes.
+    ///
+    /// The counts must start at zero, be non-decreasing and have one entry more than the
+    /// communicator has processes. Agreement between the processes is checked with an
+    /// allreduce over a hash of the counts. Returns [Error::InconsistentLayout] on all
+    /// processes if any check fails. This is a collective operation.
+    pub fn validate(&self) -> Result<(), Error> {
+        let well_formed = self.counts.len() == 1 + self.comm.size() as usize
+            && self.counts[0] == 0
+            && self.counts.windows(2).all(|pair| pair[0] <= pair[1]);
+
+        // The maximum of the hash and of its complement give the maximum and the minimum of
+        // the hashes over all processes in a single reduction.
+        let hash = counts_hash(&self.counts);
+        let local = [hash, !hash, !well_formed as u64];
+        let mut global = [0; 3];
+        self.comm()
+            .all_reduce_into(&local[..], &mut global[..], SystemOperation::max());
+
+        if global[0] == !global[1] && global[2] == 0 {
+            Ok(())
+        } else {
+            Err(Error::InconsistentLayout)
+        }
+    }
+
     /// The cumulative sum of indices over the ranks.
     ///
     /// The number of indices on rank is is counts[1 + i] - counts[i].
@@ -195,41 +318,260 @@ impl<'a, C: Communicator> IndexLayout<'a, C> {
     }
 
     /// Get the rank of a given index.
+    ///
+    /// Returns `None` if the index is out of bounds. Ranks without indices are skipped.
     pub fn rank_from_index(&self, index: usize) -> Option<usize> {
-        for (count_index, &count) in self.counts()[1..].iter().enumerate() {
-            if index < count {
-                return Some(count_index);
-            }
+        // The first rank whose range ends after the index owns it. Empty ranks end at the
+        // same position as their predecessor and are therefore never returned.
+        let rank = self.counts()[1..].partition_point(|&count| count <= index);
+        if rank < self.comm().size() as usize {
+            Some(rank)
+        } else {
+            None
+        }
+    }
+
+    /// Get the ranks of several indices.
+    ///
+    /// Sorted indices are processed in a single pass over the ranks. Panics if an index is
+    /// out of bounds. See [IndexLayout::try_ranks_from_indices] for a non-panicking version.
+    pub fn ranks_from_indices(&self, indices: &[usize]) -> Vec<usize> {
+        self.try_ranks_from_indices(indices)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Get the ranks of several indices.
+    ///
+    /// Returns [Error::IndexOutOfRange] for the first index that is out of bounds.
+    pub fn try_ranks_from_indices(&self, indices: &[usize]) -> Result<Vec<usize>, Error> {
+        let out_of_bounds = |index: usize| Error::IndexOutOfRange {
+            index,
+            bound: self.number_of_global_indices(),
+        };
+
+        if indices.windows(2).all(|pair| pair[0] <= pair[1]) {
+            let ends = &self.counts()[1..];
+            let mut rank = 0;
+            indices
+                .iter()
+                .map(|&index| {
+                    while rank < ends.len() && ends[rank] <= index {
+                        rank += 1;
+                    }
+                    if rank == ends.len() {
+                        Err(out_of_bounds(index))
+                    } else {
+                        Ok(rank)
+                    }
+                })
+                .collect()
+        } else {
+            indices
+                .iter()
+                .map(|&index| {
+                    self.rank_from_index(index)
+                        .ok_or_else(|| out_of_bounds(index))
+                })
+                .collect()
         }
-        None
+    }
+
+    /// Convert several global indices to local indices on a given rank.
+    ///
+    /// An entry is `None` if the corresponding index does not exist on the rank.
+    pub fn global2local_many(&self, rank: usize, indices: &[usize]) -> Vec<Option<usize>> {
+        indices
+            .iter()
+            .map(|&index| self.global2local(rank, index))
+            .collect()
+    }
+
+    /// Convert several local indices on the current process to global indices.
+    ///
+    /// An entry is `None` if the corresponding index is out of bounds.
+    pub fn local2global_many(&self, indices: &[usize]) -> Vec<Option<usize>> {
+        indices
+            .iter()
+            .map(|&index| self.local2global(index))
+            .collect()
     }
 
     /// Remap indices from one layout to another.
+    ///
+    /// Panics if the arguments are invalid. See [IndexLayout::try_remap] for a non-panicking
+    /// version.
     pub fn remap<T: Equivalence>(&self, other: &IndexLayout<'a, C>, data: &[T]) -> Vec<T> {
-        assert_eq!(data.len(), self.number_of_local_indices());
-        assert_eq!(
-            self.number_of_global_indices(),
-            other.number_of_global_indices()
-        );
+        self.try_remap(other, data)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Remap indices from one layout to another.
+    ///
+    /// Returns [Error::SizeMismatch] if `data` does not have one value for each local index or
+    /// if the layouts have different numbers of global indices. The processes agree on the
+    /// result of the checks before any data is exchanged, so that all processes return
+    /// [Error::FailedOnOtherRanks] if the arguments are invalid on another process. Returns
+    /// [Error::Mpi] if MPI reports an error during the exchange.
+    pub fn try_remap<T: Equivalence>(
+        &self,
+        other: &IndexLayout<'a, C>,
+        data: &[T],
+    ) -> Result<Vec<T>, Error> {
+        self.try_remap_by_chunks(other, data, 1)
+    }
 
-        let my_range = self.local_range();
+    /// Remap indices with `chunk_size` values per index from one layout to another.
+    ///
+    /// Panics if the arguments are invalid. See [IndexLayout::try_remap_by_chunks] for a
+    /// non-panicking version.
+    pub fn remap_by_chunks<T: Equivalence>(
+        &self,
+        other: &IndexLayout<'a, C>,
+        data: &[T],
+        chunk_size: usize,
+    ) -> Vec<T> {
+        self.try_remap_by_chunks(other, data, chunk_size)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Remap indices with `chunk_size` values per index from one layout to another.
+    ///
+    /// Returns [Error::SizeMismatch] if `data` does not have `chunk_size` values for each local
+    /// index or if the layouts have different numbers of global indices. See
+    /// [IndexLayout::try_remap] for the behaviour in case of an error.
+    pub fn try_remap_by_chunks<T: Equivalence>(
+        &self,
+        other: &IndexLayout<'a, C>,
+        data: &[T],
+        chunk_size: usize,
+    ) -> Result<Vec<T>, Error> {
+        agree_collectively(
+            self.comm(),
+            check_size(chunk_size * self.number_of_local_indices(), data.len()).and(check_size(
+                self.number_of_global_indices(),
+                other.number_of_global_indices(),
+            )),
+        )?;
+
+        let nelems = chunk_size * other.number_of_local_indices();
+        let mut output = Vec::<T>::with_capacity(nelems);
+        let out_buf: &mut [T] = unsafe { std::mem::transmute(output.spare_capacity_mut()) };
 
-        let other_bins = (0..other.comm().size() as usize)
-            .map(|rank| other.index_range(rank).unwrap().0)
-            .collect_vec();
+        LayoutRemap::new(self, other).try_remap_into(data, &mut out_buf[..nelems], chunk_size)?;
 
-        let sorted_keys = (my_range.0..my_range.1).collect_vec();
+        unsafe { output.set_len(nelems) };
+        Ok(output)
+    }
 
-        let counts = crate::array_tools::sort_to_bins(&sorted_keys, &other_bins)
+    /// Remap indices with `chunk_size` values per index into a given buffer.
+    ///
+    /// `out_data` receives the values of the local indices of `other`. For repeated remaps
+    /// between the same layouts create a [LayoutRemap] once instead.
+    pub fn remap_into<T: Equivalence>(
+        &self,
+        other: &IndexLayout<'a, C>,
+        data: &[T],
+        out_data: &mut [T],
+        chunk_size: usize,
+    ) {
+        LayoutRemap::new(self, other).remap_into(data, out_data, chunk_size);
+    }
+
+    /// Restrict the layout to a sub-communicator.
+    ///
+    /// `subcomm` is a sub-communicator of the communicator of this layout on the processes
+    /// that take part in it and `None` on all other processes, e.g. the result of splitting the
+    /// communicator. The global indices are distributed evenly over the processes of the
+    /// sub-communicator in the order of their ranks there.
+    ///
+    /// Returns the new layout, `None` on processes outside the sub-communicator, and a
+    /// [LayoutMover] whose forward direction moves data from this layout to the new layout.
+    /// This is a collective operation on the communicator of this layout.
+    pub fn restrict_to<'b, D: Communicator, S: Into<CommRef<'b, D>>>(
+        &self,
+        subcomm: Option<S>,
+    ) -> (Option<IndexLayout<'b, D>>, LayoutMover<'a, C>) {
+        let subcomm = subcomm.map(Into::into);
+
+        let restricted = subcomm.map(|subcomm| {
+            IndexLayout::from_equidistributed_chunks(self.number_of_global_indices(), 1, subcomm)
+        });
+
+        let source_ranges = (0..self.comm().size() as usize)
+            .map(|rank| self.index_range(rank).unwrap())
+            .collect::<Vec<_>>();
+        let target_ranges = gather_ranges(restricted.as_ref(), self.comm());
+
+        let mover = LayoutMover::new(&source_ranges, &target_ranges, self.comm.clone());
+
+        (restricted, mover)
+    }
+
+    /// Extend a layout on a sub-communicator to a parent communicator.
+    ///
+    /// `restricted` is the layout on the sub-communicator on the processes that take part in it
+    /// and `None` on all other processes. The global indices are distributed evenly over all
+    /// processes of `parent_comm`.
+    ///
+    /// Returns the new layout and a [LayoutMover] whose forward direction moves data from the
+    /// restricted layout to the new layout. This is a collective operation on `parent_comm`.
+    pub fn extend_to<'b, D: Communicator>(
+        restricted: Option<&IndexLayout<'b, D>>,
+        parent_comm: impl Into<CommRef<'a, C>>,
+    ) -> (Self, LayoutMover<'a, C>) {
+        let parent_comm = parent_comm.into();
+
+        let source_ranges = gather_ranges(restricted, &*parent_comm);
+        let nindices = source_ranges
             .iter()
-            .map(|&key| key as i32)
-            .collect_vec();
+            .map(|&(_, last)| last)
+            .max()
+            .unwrap_or(0);
+
+        let extended = Self::from_equidistributed_chunks(nindices, 1, parent_comm.clone());
+        let target_ranges = (0..parent_comm.size() as usize)
+            .map(|rank| extended.index_range(rank).unwrap())
+            .collect::<Vec<_>>();
 
-        redistribute(data, &counts, other.comm())
+        let mover = LayoutMover::new(&source_ranges, &target_ranges, parent_comm);
+
+        (extended, mover)
     }
 
     /// Return the communicator.
     pub fn comm(&self) -> &C {
-        self.comm
+        &self.comm
+    }
+
+    /// Return the borrowed or shared communicator.
+    pub fn comm_ref(&self) -> &CommRef<'a, C> {
+        &self.comm
     }
 }
+
+/// FNV-1a hash of the counts of a layout.
+///
+/// The hash must be identical on all processes, so no randomly seeded hasher can be used.
+fn counts_hash(counts: &[usize]) -> u64 {
+    counts
+        .iter()
+        .flat_map(|&count| (count as u64).to_le_bytes())
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// Gather the local ranges of a layout that only exists on some processes of `comm`.
+///
+/// Processes without the layout own the empty range `(0, 0)`.
+fn gather_ranges<D: Communicator, C: Communicator>(
+    layout: Option<&IndexLayout<'_, D>>,
+    comm: &C,
+) -> Vec<(usize, usize)> {
+    let local_range = layout.map_or((0, 0), |layout| layout.local_range());
+
+    let mut ranges = vec![0; 2 * comm.size() as usize];
+    comm.all_gather_into(&[local_range.0, local_range.1][..], &mut ranges[..]);
+
+    ranges.chunks(2).map(|range| (range[0], range[1])).collect()
+}
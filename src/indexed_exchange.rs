@@ -0,0 +1,452 @@
+//! Ghost exchanges with MPI derived datatypes.
+//!
+//! The exchange methods of a [GhostCommunicator] work on contiguous send and receive buffers.
+//! Users therefore usually copy owned entries into a send buffer before an exchange and
+//! scatter the received entries afterwards. An [IndexedExchange] instead describes the
+//! positions of the send and receive indices in user arrays with indexed MPI datatypes
+//! (`MPI_Type_create_indexed_block`), so that MPI sends straight from and receives straight
+//! into these arrays.
+//!
+//! The datatypes depend on the MPI datatype of the values and the chunk size. They are created
+//! on first use and cached for subsequent exchanges. Exchanges that are repeated with the same value type
+//! and chunk size can instead keep their own datatypes, which avoids the lookup in the cache.
+
+use std::cell::{RefCell, RefMut};
+use std::collections::HashMap;
+use std::os::raw::c_void;
+
+use itertools::{izip, Itertools};
+use mpi::traits::{AsRaw, Communicator, Equivalence};
+
+use crate::error::{check_mpi, Error};
+use crate::{GhostCommunicator, GhostCommunicatorBackend};
+
+/// Indexed datatypes for one value type and chunk size.
+///
+/// The request buffer is used by the point-to-point backend.
+pub(crate) struct IndexedDatatypes {
+    chunk_size: usize,
+    send_types: Vec<mpi_sys::MPI_Datatype>,
+    receive_types: Vec<mpi_sys::MPI_Datatype>,
+    requests: Vec<mpi_sys::MPI_Request>,
+}
+
+impl Drop for IndexedDatatypes {
+    fn drop(&mut self) {
+        for datatype in self
+            .send_types
+            .iter_mut()
+            .chain(self.receive_types.iter_mut())
+        {
+            unsafe {
+                mpi_sys::MPI_Type_free(datatype);
+            }
+        }
+    }
+}
+
+/// Ghost exchange that sends from and receives into user arrays.
+///
+/// An indexed exchange belongs to the ghost communicator it was created with. The exchange
+/// methods must be called with this ghost communicator.
+pub struct IndexedExchange {
+    // The forward communicator of the ghost communicator, which identifies it.
+    comm: mpi_sys::MPI_Comm,
+    send_positions: Vec<i32>,
+    receive_positions: Vec<i32>,
+    send_counts: Vec<i32>,
+    receive_counts: Vec<i32>,
+    // Each neighbour exchanges a single element of its datatype starting at the beginning of
+    // the arrays. The counts and displacements are long enough for all neighbours.
+    unit_counts: Vec<i32>,
+    zero_displacements: Vec<mpi_sys::MPI_Aint>,
+    // Cached datatypes by the MPI datatype of the values and the chunk size.
+    datatypes: RefCell<HashMap<(mpi_sys::MPI_Datatype, usize), IndexedDatatypes>>,
+}
+
+impl IndexedExchange {
+    /// Create a new indexed exchange.
+    ///
+    /// `send_positions[i]` is the position of the entry for the `i`-th send index of the
+    /// `ghost_communicator` in the array that values are sent from in a forward exchange.
+    /// `receive_positions[i]` is the position of the entry for the `i`-th receive index in the
+    /// array that values are received into. Positions count entries, i.e. chunks of values.
+    /// Receive positions must be unique. For backward exchanges the send positions of different
+    /// processes must not overlap either.
+    pub fn new<I: Default + Copy + Equivalence, C: Communicator>(
+        ghost_communicator: &GhostCommunicator<'_, I, C>,
+        send_positions: &[usize],
+        receive_positions: &[usize],
+    ) -> Self {
+        assert_eq!(send_positions.len(), ghost_communicator.total_send_count());
+        assert_eq!(
+            receive_positions.len(),
+            ghost_communicator.total_receive_count()
+        );
+        debug_assert!(
+            receive_positions.iter().all_unique(),
+            "Receive positions must be unique."
+        );
+
+        let number_of_neighbours = std::cmp::max(
+            ghost_communicator.out_ranks().len(),
+            ghost_communicator.in_ranks().len(),
+        );
+
+        Self {
+            comm: ghost_communicator.forward_comm().as_raw(),
+            send_positions: send_positions.iter().map(|&p| p as i32).collect(),
+            receive_positions: receive_positions.iter().map(|&p| p as i32).collect(),
+            send_counts: ghost_communicator.send_counts().to_vec(),
+            receive_counts: ghost_communicator.receive_counts().to_vec(),
+            unit_counts: vec![1; number_of_neighbours],
+            zero_displacements: vec![0; number_of_neighbours],
+            datatypes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Forward send values.
+    ///
+    /// The entries at the send positions of `out_values` are sent to the receive positions of
+    /// `in_values` on the receiving processes. All other values are left untouched.
+    pub fn forward_send_values_by_chunks<
+        T: Equivalence,
+        I: Default + Copy + Equivalence,
+        C: Communicator,
+    >(
+        &self,
+        ghost_communicator: &GhostCommunicator<'_, I, C>,
+        out_values: &[T],
+        in_values: &mut [T],
+        chunk_size: usize,
+    ) {
+        self.try_forward_send_values_by_chunks(
+            ghost_communicator,
+            out_values,
+            in_values,
+            chunk_size,
+        )
+        .unwrap_or_else(|error| panic!("{}", error));
+    }
+
+    /// Forward send values.
+    ///
+    /// Does the same exchange as [IndexedExchange::forward_send_values_by_chunks] and returns
+    /// [Error::Mpi] if MPI reports an error.
+    pub(crate) fn try_forward_send_values_by_chunks<
+        T: Equivalence,
+        I: Default + Copy + Equivalence,
+        C: Communicator,
+    >(
+        &self,
+        ghost_communicator: &GhostCommunicator<'_, I, C>,
+        out_values: &[T],
+        in_values: &mut [T],
+        chunk_size: usize,
+    ) -> Result<(), Error> {
+        self.forward_send_values_with_datatypes(
+            ghost_communicator,
+            out_values,
+            in_values,
+            &mut self.datatypes::<T>(chunk_size),
+        )
+    }
+
+    /// Forward send values with datatypes from [IndexedExchange::create_datatypes].
+    ///
+    /// Does the same exchange as [IndexedExchange::forward_send_values_by_chunks] without
+    /// looking up the datatypes in the cache and without allocating. Returns [Error::Mpi] if MPI
+    /// reports an error.
+    pub(crate) fn forward_send_values_with_datatypes<
+        T: Equivalence,
+        I: Default + Copy + Equivalence,
+        C: Communicator,
+    >(
+        &self,
+        ghost_communicator: &GhostCommunicator<'_, I, C>,
+        out_values: &[T],
+        in_values: &mut [T],
+        datatypes: &mut IndexedDatatypes,
+    ) -> Result<(), Error> {
+        self.assert_ghost_communicator(ghost_communicator);
+        assert!(fits(
+            &self.send_positions,
+            out_values.len(),
+            datatypes.chunk_size
+        ));
+        assert!(fits(
+            &self.receive_positions,
+            in_values.len(),
+            datatypes.chunk_size
+        ));
+
+        let IndexedDatatypes {
+            send_types,
+            receive_types,
+            requests,
+            ..
+        } = datatypes;
+
+        match ghost_communicator.backend() {
+            GhostCommunicatorBackend::NeighbourhoodCollective => neighbor_alltoallw(
+                out_values.as_ptr() as *const c_void,
+                send_types,
+                in_values.as_mut_ptr() as *mut c_void,
+                receive_types,
+                &self.unit_counts,
+                &self.zero_displacements,
+                ghost_communicator.forward_comm().as_raw(),
+            ),
+            GhostCommunicatorBackend::PointToPoint => point_to_point_exchange(
+                requests,
+                out_values,
+                ghost_communicator.out_ranks(),
+                send_types,
+                in_values,
+                ghost_communicator.in_ranks(),
+                receive_types,
+                ghost_communicator.forward_comm().as_raw(),
+            ),
+        }
+    }
+
+    /// Backward send values.
+    ///
+    /// The entries at the receive positions of `out_values` are sent back to the send positions
+    /// of `in_values` on the owning processes. All other values are left untouched.
+    pub fn backward_send_values_by_chunks<
+        T: Equivalence,
+        I: Default + Copy + Equivalence,
+        C: Communicator,
+    >(
+        &self,
+        ghost_communicator: &GhostCommunicator<'_, I, C>,
+        out_values: &[T],
+        in_values: &mut [T],
+        chunk_size: usize,
+    ) {
+        self.try_backward_send_values_by_chunks(
+            ghost_communicator,
+            out_values,
+            in_values,
+            chunk_size,
+        )
+        .unwrap_or_else(|error| panic!("{}", error));
+    }
+
+    /// Backward send values.
+    ///
+    /// Does the same exchange as [IndexedExchange::backward_send_values_by_chunks] and returns
+    /// [Error::Mpi] if MPI reports an error.
+    pub(crate) fn try_backward_send_values_by_chunks<
+        T: Equivalence,
+        I: Default + Copy + Equivalence,
+        C: Communicator,
+    >(
+        &self,
+        ghost_communicator: &GhostCommunicator<'_, I, C>,
+        out_values: &[T],
+        in_values: &mut [T],
+        chunk_size: usize,
+    ) -> Result<(), Error> {
+        self.assert_ghost_communicator(ghost_communicator);
+        assert!(fits(&self.receive_positions, out_values.len(), chunk_size));
+        assert!(fits(&self.send_positions, in_values.len(), chunk_size));
+
+        let mut datatypes = self.datatypes::<T>(chunk_size);
+        let IndexedDatatypes {
+            send_types,
+            receive_types,
+            requests,
+            ..
+        } = &mut *datatypes;
+
+        match ghost_communicator.backend() {
+            GhostCommunicatorBackend::NeighbourhoodCollective => neighbor_alltoallw(
+                out_values.as_ptr() as *const c_void,
+                receive_types,
+                in_values.as_mut_ptr() as *mut c_void,
+                send_types,
+                &self.unit_counts,
+                &self.zero_displacements,
+                ghost_communicator.backward_comm().as_raw(),
+            ),
+            GhostCommunicatorBackend::PointToPoint => point_to_point_exchange(
+                requests,
+                out_values,
+                ghost_communicator.in_ranks(),
+                receive_types,
+                in_values,
+                ghost_communicator.out_ranks(),
+                send_types,
+                ghost_communicator.backward_comm().as_raw(),
+            ),
+        }
+    }
+
+    /// Check that `ghost_communicator` is the one this exchange was created with.
+    fn assert_ghost_communicator<I: Default + Copy + Equivalence, C: Communicator>(
+        &self,
+        ghost_communicator: &GhostCommunicator<'_, I, C>,
+    ) {
+        assert!(
+            ghost_communicator.forward_comm().as_raw() == self.comm,
+            "The indexed exchange was created with a different ghost communicator."
+        );
+    }
+
+    /// Create the datatypes for values of type `T` with `chunk_size` values per entry.
+    pub(crate) fn create_datatypes<T: Equivalence>(&self, chunk_size: usize) -> IndexedDatatypes {
+        IndexedDatatypes {
+            chunk_size,
+            send_types: indexed_datatypes::<T>(&self.send_positions, &self.send_counts, chunk_size),
+            receive_types: indexed_datatypes::<T>(
+                &self.receive_positions,
+                &self.receive_counts,
+                chunk_size,
+            ),
+            requests: Vec::with_capacity(self.send_counts.len() + self.receive_counts.len()),
+        }
+    }
+
+    /// Return the cached datatypes, creating the datatypes for `T` and `chunk_size` if needed.
+    fn datatypes<T: Equivalence>(&self, chunk_size: usize) -> RefMut<'_, IndexedDatatypes> {
+        RefMut::map(self.datatypes.borrow_mut(), |cache| {
+            cache
+                .entry((
+                    <T as Equivalence>::equivalent_datatype().as_raw(),
+                    chunk_size,
+                ))
+                .or_insert_with(|| self.create_datatypes::<T>(chunk_size))
+        })
+    }
+}
+
+/// Check that all chunks at the given positions lie inside an array of length `len`.
+fn fits(positions: &[i32], len: usize, chunk_size: usize) -> bool {
+    positions
+        .iter()
+        .all(|&position| (1 + position as usize) * chunk_size <= len)
+}
+
+/// Create one indexed datatype for each neighbour.
+///
+/// The positions of the neighbours are stored consecutively with the given counts.
+fn indexed_datatypes<T: Equivalence>(
+    positions: &[i32],
+    counts: &[i32],
+    chunk_size: usize,
+) -> Vec<mpi_sys::MPI_Datatype> {
+    let mut datatypes = Vec::with_capacity(counts.len());
+    let mut start = 0;
+
+    for &count in counts {
+        // Displacements are given in multiples of the extent of the values.
+        let displacements = positions[start..start + count as usize]
+            .iter()
+            .map(|&position| position * chunk_size as i32)
+            .collect::<Vec<_>>();
+        start += count as usize;
+
+        unsafe {
+            let mut datatype = mpi_sys::RSMPI_DATATYPE_NULL;
+            mpi_sys::MPI_Type_create_indexed_block(
+                count,
+                chunk_size as i32,
+                displacements.as_ptr(),
+                <T as Equivalence>::equivalent_datatype().as_raw(),
+                &mut datatype,
+            );
+            mpi_sys::MPI_Type_commit(&mut datatype);
+            datatypes.push(datatype);
+        }
+    }
+
+    datatypes
+}
+
+/// Blocking neighbourhood exchange with one datatype per neighbour.
+///
+/// Each neighbour exchanges a single element of its datatype starting at the beginning of the
+/// arrays, as given by `unit_counts` and `zero_displacements`.
+fn neighbor_alltoallw(
+    out_values: *const c_void,
+    send_types: &[mpi_sys::MPI_Datatype],
+    in_values: *mut c_void,
+    receive_types: &[mpi_sys::MPI_Datatype],
+    unit_counts: &[i32],
+    zero_displacements: &[mpi_sys::MPI_Aint],
+    comm: mpi_sys::MPI_Comm,
+) -> Result<(), Error> {
+    check_mpi(unsafe {
+        mpi_sys::MPI_Neighbor_alltoallw(
+            out_values,
+            unit_counts.as_ptr(),
+            zero_displacements.as_ptr(),
+            send_types.as_ptr(),
+            in_values,
+            unit_counts.as_ptr(),
+            zero_displacements.as_ptr(),
+            receive_types.as_ptr(),
+            comm,
+        )
+    })
+}
+
+/// Blocking point-to-point exchange with one datatype per neighbour.
+///
+/// The requests are stored in `requests`, which is cleared first. If posting a message fails,
+/// the messages that were posted are still completed before the error is returned.
+#[allow(clippy::too_many_arguments)]
+fn point_to_point_exchange<T>(
+    requests: &mut Vec<mpi_sys::MPI_Request>,
+    out_values: &[T],
+    target_ranks: &[i32],
+    send_types: &[mpi_sys::MPI_Datatype],
+    in_values: &mut [T],
+    source_ranks: &[i32],
+    receive_types: &[mpi_sys::MPI_Datatype],
+    comm: mpi_sys::MPI_Comm,
+) -> Result<(), Error> {
+    requests.clear();
+    let mut result = Ok(());
+
+    unsafe {
+        for (&source, &datatype) in izip!(source_ranks, receive_types) {
+            let mut request = mpi_sys::RSMPI_REQUEST_NULL;
+            let code = mpi_sys::MPI_Irecv(
+                in_values.as_mut_ptr() as *mut c_void,
+                1,
+                datatype,
+                source,
+                0,
+                comm,
+                &mut request,
+            );
+            result = result.and(check_mpi(code));
+            requests.push(request);
+        }
+
+        for (&target, &datatype) in izip!(target_ranks, send_types) {
+            let mut request = mpi_sys::RSMPI_REQUEST_NULL;
+            let code = mpi_sys::MPI_Isend(
+                out_values.as_ptr() as *const c_void,
+                1,
+                datatype,
+                target,
+                0,
+                comm,
+                &mut request,
+            );
+            result = result.and(check_mpi(code));
+            requests.push(request);
+        }
+
+        let completed = check_mpi(mpi_sys::MPI_Waitall(
+            requests.len() as i32,
+            requests.as_mut_ptr(),
+            mpi_sys::RSMPI_STATUSES_IGNORE,
+        ));
+        result.and(completed)
+    }
+}
@@ -0,0 +1,198 @@
+//! Common interface of index layouts.
+//!
+//! An index layout describes which process owns which global index. The [IndexLayout] assigns
+//! contiguous blocks of indices to processes, while the
+//! [GeneralIndexLayout](crate::GeneralIndexLayout) supports arbitrary sets of owned indices.
+//! Both implement the [Layout] trait, so that ghost communicators, data mappers and data
+//! permutations can be set up for either of them.
+
+use itertools::{izip, Itertools};
+use mpi::traits::{Communicator, Equivalence};
+
+use crate::array_tools::redistribute;
+use crate::comm_ref::CommRef;
+use crate::error::Error;
+use crate::IndexLayout;
+
+/// Distribution of global indices among the processes of a communicator.
+///
+/// The owned indices of a process are numbered locally from `0` to
+/// `number_of_local_indices() - 1`.
+pub trait Layout<'a> {
+    /// The communicator type.
+    type Comm: Communicator + 'a;
+
+    /// Return the borrowed or shared communicator.
+    fn comm_ref(&self) -> &CommRef<'a, Self::Comm>;
+
+    /// Return the communicator.
+    fn comm(&self) -> &Self::Comm;
+
+    /// The number of global indices.
+    fn number_of_global_indices(&self) -> usize;
+
+    /// The number of indices owned by the current process.
+    fn number_of_local_indices(&self) -> usize;
+
+    /// The range of global indices owned by the current process.
+    ///
+    /// Returns `None` if the owned indices do not form a contiguous range.
+    fn local_range(&self) -> Option<(usize, usize)>;
+
+    /// Convert a local index on the current process to a global index.
+    ///
+    /// Returns `None` if `index` is out of bounds.
+    fn local2global(&self, index: usize) -> Option<usize>;
+
+    /// Convert a global index to a local index on the current process.
+    ///
+    /// Returns `None` if the index is not owned by the current process.
+    fn global2local_owned(&self, index: usize) -> Option<usize>;
+
+    /// Return true if the global index is owned by the current process.
+    fn is_owned(&self, index: usize) -> bool {
+        self.global2local_owned(index).is_some()
+    }
+
+    /// Get the owning ranks of several global indices.
+    ///
+    /// This method may communicate and must be called on all processes of the communicator
+    /// together. Panics if an index is out of bounds.
+    fn ranks_from_indices(&self, indices: &[usize]) -> Vec<usize>;
+
+    /// Get the owning ranks of several global indices.
+    ///
+    /// Returns [Error::IndexOutOfRange] or [Error::UnknownOwner] for the first index that has
+    /// no owner. This method may communicate and must be called on all processes of the
+    /// communicator together, also if it fails on some of them.
+    fn try_ranks_from_indices(&self, indices: &[usize]) -> Result<Vec<usize>, Error>;
+}
+
+impl<'a, C: Communicator> Layout<'a> for IndexLayout<'a, C> {
+    type Comm = C;
+
+    fn comm_ref(&self) -> &CommRef<'a, C> {
+        IndexLayout::comm_ref(self)
+    }
+
+    fn comm(&self) -> &C {
+        IndexLayout::comm(self)
+    }
+
+    fn number_of_global_indices(&self) -> usize {
+        IndexLayout::number_of_global_indices(self)
+    }
+
+    fn number_of_local_indices(&self) -> usize {
+        IndexLayout::number_of_local_indices(self)
+    }
+
+    fn local_range(&self) -> Option<(usize, usize)> {
+        Some(IndexLayout::local_range(self))
+    }
+
+    fn local2global(&self, index: usize) -> Option<usize> {
+        IndexLayout::local2global(self, index)
+    }
+
+    fn global2local_owned(&self, index: usize) -> Option<usize> {
+        self.global2local(self.comm().rank() as usize, index)
+    }
+
+    fn ranks_from_indices(&self, indices: &[usize]) -> Vec<usize> {
+        IndexLayout::ranks_from_indices(self, indices)
+    }
+
+    fn try_ranks_from_indices(&self, indices: &[usize]) -> Result<Vec<usize>, Error> {
+        IndexLayout::try_ranks_from_indices(self, indices)
+    }
+}
+
+/// Move data between two layouts of the same global indices.
+///
+/// `data` holds the values of the local indices of `source`. Returns the values of the local
+/// indices of `target`. The values are sent to their new owners together with their global
+/// indices via [redistribute]. This is a collective operation.
+pub(crate) fn remap_data<'a, S, D, T>(source: &S, target: &D, data: &[T]) -> Vec<T>
+where
+    S: Layout<'a>,
+    D: Layout<'a, Comm = S::Comm>,
+    T: Equivalence + Copy,
+{
+    assert_eq!(data.len(), source.number_of_local_indices());
+    assert_eq!(
+        source.number_of_global_indices(),
+        target.number_of_global_indices()
+    );
+
+    let comm = source.comm();
+    let rank = comm.rank() as usize;
+
+    let global_indices = (0..source.number_of_local_indices())
+        .map(|local_index| source.local2global(local_index).unwrap())
+        .collect_vec();
+    let target_ranks = target.ranks_from_indices(&global_indices);
+
+    // Sort the values by their target ranks.
+
+    let sorted_local_indices = (0..global_indices.len())
+        .sorted_by_key(|&local_index| target_ranks[local_index])
+        .collect_vec();
+
+    let mut counts = vec![0; comm.size() as usize];
+    for &target_rank in &target_ranks {
+        counts[target_rank] += 1;
+    }
+
+    let sorted_global_indices = sorted_local_indices
+        .iter()
+        .map(|&local_index| global_indices[local_index])
+        .collect_vec();
+
+    let sorted_data = sorted_local_indices
+        .iter()
+        .map(|&local_index| data[local_index])
+        .collect_vec();
+
+    let received_indices = redistribute(&sorted_global_indices, &counts, comm);
+    let received_data = redistribute(&sorted_data, &counts, comm);
+
+    // Every local index of the target must receive exactly one value. This only fails if the
+    // layouts are inconsistent across the processes.
+
+    let number_of_local_indices = target.number_of_local_indices();
+    assert_eq!(
+        received_indices.len(),
+        number_of_local_indices,
+        "Process {} received {} values for {} local indices.",
+        rank,
+        received_indices.len(),
+        number_of_local_indices
+    );
+
+    let mut output = Vec::<T>::with_capacity(number_of_local_indices);
+    let output_buffer = output.spare_capacity_mut();
+    let mut written = vec![false; number_of_local_indices];
+
+    for (&global_index, value) in izip!(&received_indices, received_data) {
+        let local_index = target.global2local_owned(global_index).unwrap_or_else(|| {
+            panic!(
+                "Process {} received index {} that it does not own.",
+                rank, global_index
+            )
+        });
+        assert!(
+            !written[local_index],
+            "Process {} received index {} more than once.",
+            rank, global_index
+        );
+        written[local_index] = true;
+        output_buffer[local_index].write(value);
+    }
+
+    // As many values as local indices were received without duplicates, so every local
+    // index has been written.
+
+    unsafe { output.set_len(number_of_local_indices) };
+    output
+}
@@ -0,0 +1,155 @@
+//! Moving data between layouts on different communicators.
+//!
+//! A [LayoutMover] moves data between two contiguous distributions of the same global indices
+//! over the processes of a parent communicator. Processes may own indices in only one of the
+//! distributions. This is used by [IndexLayout::restrict_to](crate::IndexLayout::restrict_to)
+//! and [IndexLayout::extend_to](crate::IndexLayout::extend_to) to move data between a layout
+//! on a parent communicator and a layout on a sub-communicator. The data is exchanged over
+//! the parent communicator with [redistribute].
+
+use itertools::izip;
+use mpi::traits::{Communicator, Equivalence};
+
+use crate::array_tools::redistribute;
+use crate::comm_ref::CommRef;
+use crate::ghost_communicator::chunked;
+
+/// Moves data between two distributions of global indices over a parent communicator.
+pub struct LayoutMover<'a, C: Communicator> {
+    // For each process, the number of local source indices sent to it and the local position
+    // of the first of them.
+    send_counts: Vec<i32>,
+    send_offsets: Vec<usize>,
+    // For each process, the number of local target indices received from it and the local
+    // position of the first of them.
+    receive_counts: Vec<i32>,
+    receive_offsets: Vec<usize>,
+    number_of_source_indices: usize,
+    number_of_target_indices: usize,
+    comm: CommRef<'a, C>,
+}
+
+impl<'a, C: Communicator> LayoutMover<'a, C> {
+    /// Create a new mover.
+    ///
+    /// `source_ranges[i]` and `target_ranges[i]` are the global index ranges owned by the
+    /// process with rank `i` of the parent communicator in the source and target distribution.
+    pub(crate) fn new(
+        source_ranges: &[(usize, usize)],
+        target_ranges: &[(usize, usize)],
+        comm: CommRef<'a, C>,
+    ) -> Self {
+        let rank = comm.rank() as usize;
+
+        let (send_counts, send_offsets) = overlaps(source_ranges[rank], target_ranges);
+        let (receive_counts, receive_offsets) = overlaps(target_ranges[rank], source_ranges);
+
+        Self {
+            send_counts,
+            send_offsets,
+            receive_counts,
+            receive_offsets,
+            number_of_source_indices: source_ranges[rank].1 - source_ranges[rank].0,
+            number_of_target_indices: target_ranges[rank].1 - target_ranges[rank].0,
+            comm,
+        }
+    }
+
+    /// The number of local indices in the source distribution.
+    pub fn number_of_source_indices(&self) -> usize {
+        self.number_of_source_indices
+    }
+
+    /// The number of local indices in the target distribution.
+    pub fn number_of_target_indices(&self) -> usize {
+        self.number_of_target_indices
+    }
+
+    /// Move data with `chunk_size` values per index from the source to the target distribution.
+    ///
+    /// This is a collective operation on the parent communicator.
+    pub fn forward<T: Equivalence + Copy>(&self, data: &[T], chunk_size: usize) -> Vec<T> {
+        assert_eq!(data.len(), chunk_size * self.number_of_source_indices);
+        move_data(
+            data,
+            (&self.send_counts, &self.send_offsets),
+            (&self.receive_counts, &self.receive_offsets),
+            self.number_of_target_indices,
+            chunk_size,
+            &*self.comm,
+        )
+    }
+
+    /// Move data with `chunk_size` values per index from the target back to the source
+    /// distribution.
+    ///
+    /// This is a collective operation on the parent communicator.
+    pub fn backward<T: Equivalence + Copy>(&self, data: &[T], chunk_size: usize) -> Vec<T> {
+        assert_eq!(data.len(), chunk_size * self.number_of_target_indices);
+        move_data(
+            data,
+            (&self.receive_counts, &self.receive_offsets),
+            (&self.send_counts, &self.send_offsets),
+            self.number_of_source_indices,
+            chunk_size,
+            &*self.comm,
+        )
+    }
+}
+
+/// Return the overlaps of a local range with the ranges of all processes.
+///
+/// Returns the sizes of the overlaps and their offsets relative to the start of the local range.
+fn overlaps(local_range: (usize, usize), ranges: &[(usize, usize)]) -> (Vec<i32>, Vec<usize>) {
+    ranges
+        .iter()
+        .map(|&(first, last)| {
+            let start = first.max(local_range.0);
+            let end = last.min(local_range.1);
+            if start < end {
+                ((end - start) as i32, start - local_range.0)
+            } else {
+                (0, 0)
+            }
+        })
+        .unzip()
+}
+
+/// Send the given parts of `data` to all processes and place the received parts.
+fn move_data<T: Equivalence + Copy, C: Communicator>(
+    data: &[T],
+    (send_counts, send_offsets): (&[i32], &[usize]),
+    (receive_counts, receive_offsets): (&[i32], &[usize]),
+    number_of_indices: usize,
+    chunk_size: usize,
+    comm: &C,
+) -> Vec<T> {
+    // The parts sent to the processes are not necessarily in the order of the ranks, e.g. if
+    // the ranks of a sub-communicator are ordered differently from the parent communicator.
+
+    let mut send_data = Vec::<T>::with_capacity(data.len());
+    for (&count, &offset) in izip!(send_counts, send_offsets) {
+        send_data
+            .extend_from_slice(&data[chunk_size * offset..chunk_size * (offset + count as usize)]);
+    }
+
+    let received_data = redistribute(&send_data, &chunked(send_counts, chunk_size), comm);
+
+    // The received parts arrive in the order of the source ranks.
+
+    let nelems = chunk_size * number_of_indices;
+    let mut output = Vec::<T>::with_capacity(nelems);
+    let out_buf: &mut [T] = unsafe { std::mem::transmute(output.spare_capacity_mut()) };
+
+    let mut start = 0;
+    for (&count, &offset) in izip!(receive_counts, receive_offsets) {
+        let len = chunk_size * count as usize;
+        out_buf[chunk_size * offset..chunk_size * offset + len]
+            .copy_from_slice(&received_data[start..start + len]);
+        start += len;
+    }
+
+    unsafe { output.set_len(nelems) };
+
+    output
+}
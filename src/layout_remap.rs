@@ -0,0 +1,188 @@
+//! Repeated remaps between two index layouts.
+//!
+//! Both layouts of a remap assign contiguous ranges of global indices to the processes. The
+//! values that a process sends to another process are therefore a contiguous part of its data,
+//! and the values that it receives from another process are a contiguous part of the output.
+//! A [LayoutRemap] computes the counts and displacements of these parts once from the two
+//! layouts, without any communication, and then moves data with a single all-to-all exchange.
+
+use std::os::raw::c_void;
+
+use mpi::request::Scope;
+use mpi::traits::{AsRaw, Communicator, Equivalence};
+
+use crate::array_tools::displacements;
+use crate::comm_ref::CommRef;
+use crate::error::{check_mpi, Error};
+use crate::ghost_communicator::chunked;
+use crate::request::ExchangeRequest;
+use crate::IndexLayout;
+
+/// Precomputed remap from one index layout to another.
+pub struct LayoutRemap<'a, C: Communicator> {
+    send_counts: Vec<i32>,
+    send_displacements: Vec<i32>,
+    receive_counts: Vec<i32>,
+    receive_displacements: Vec<i32>,
+    number_of_source_indices: usize,
+    number_of_target_indices: usize,
+    comm: CommRef<'a, C>,
+}
+
+impl<'a, C: Communicator> LayoutRemap<'a, C> {
+    /// Create a new remap from `source` to `target`.
+    ///
+    /// The layouts must have the same number of global indices and the same communicator.
+    pub fn new(source: &IndexLayout<'a, C>, target: &IndexLayout<'a, C>) -> Self {
+        assert_eq!(
+            source.number_of_global_indices(),
+            target.number_of_global_indices()
+        );
+
+        let size = source.comm().size() as usize;
+
+        // The number of indices sent to or received from a process is the overlap of the
+        // local range with the range of that process in the other layout.
+
+        let overlaps = |local_range: (usize, usize), other: &IndexLayout<'a, C>| {
+            (0..size)
+                .map(|rank| {
+                    let (first, last) = other.index_range(rank).unwrap();
+                    last.min(local_range.1)
+                        .saturating_sub(first.max(local_range.0)) as i32
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let send_counts = overlaps(source.local_range(), target);
+        let receive_counts = overlaps(target.local_range(), source);
+
+        Self {
+            send_displacements: displacements(&send_counts),
+            receive_displacements: displacements(&receive_counts),
+            send_counts,
+            receive_counts,
+            number_of_source_indices: source.number_of_local_indices(),
+            number_of_target_indices: target.number_of_local_indices(),
+            comm: source.comm_ref().clone(),
+        }
+    }
+
+    /// The number of local indices in the source layout.
+    pub fn number_of_source_indices(&self) -> usize {
+        self.number_of_source_indices
+    }
+
+    /// The number of local indices in the target layout.
+    pub fn number_of_target_indices(&self) -> usize {
+        self.number_of_target_indices
+    }
+
+    /// Remap data with `chunk_size` values per index.
+    ///
+    /// `data` holds the values of the local indices of the source layout. Returns the values
+    /// of the local indices of the target layout. This is a collective operation.
+    pub fn remap<T: Equivalence>(&self, data: &[T], chunk_size: usize) -> Vec<T> {
+        let nelems = chunk_size * self.number_of_target_indices;
+
+        let mut output = Vec::<T>::with_capacity(nelems);
+        let out_buf: &mut [T] = unsafe { std::mem::transmute(output.spare_capacity_mut()) };
+
+        self.remap_into(data, &mut out_buf[..nelems], chunk_size);
+
+        unsafe { output.set_len(nelems) };
+
+        output
+    }
+
+    /// Remap data with `chunk_size` values per index into a given buffer.
+    ///
+    /// `out_data` receives the values of the local indices of the target layout. This is a
+    /// collective operation.
+    pub fn remap_into<T: Equivalence>(&self, data: &[T], out_data: &mut [T], chunk_size: usize) {
+        self.try_remap_into(data, out_data, chunk_size)
+            .unwrap_or_else(|error| panic!("{}", error));
+    }
+
+    /// Remap data with `chunk_size` values per index into a given buffer.
+    ///
+    /// Does the same exchange as [LayoutRemap::remap_into] and returns [Error::Mpi] if MPI
+    /// reports an error.
+    pub(crate) fn try_remap_into<T: Equivalence>(
+        &self,
+        data: &[T],
+        out_data: &mut [T],
+        chunk_size: usize,
+    ) -> Result<(), Error> {
+        assert_eq!(data.len(), chunk_size * self.number_of_source_indices);
+        assert_eq!(out_data.len(), chunk_size * self.number_of_target_indices);
+
+        let send_counts = chunked(&self.send_counts, chunk_size);
+        let send_displacements = chunked(&self.send_displacements, chunk_size);
+        let receive_counts = chunked(&self.receive_counts, chunk_size);
+        let receive_displacements = chunked(&self.receive_displacements, chunk_size);
+
+        check_mpi(unsafe {
+            mpi_sys::MPI_Alltoallv(
+                data.as_ptr() as *const c_void,
+                send_counts.as_ptr(),
+                send_displacements.as_ptr(),
+                <T as Equivalence>::equivalent_datatype().as_raw(),
+                out_data.as_mut_ptr() as *mut c_void,
+                receive_counts.as_ptr(),
+                receive_displacements.as_ptr(),
+                <T as Equivalence>::equivalent_datatype().as_raw(),
+                self.comm.as_raw(),
+            )
+        })
+    }
+
+    /// Start a non-blocking remap into a given buffer.
+    ///
+    /// Starts the same exchange as [LayoutRemap::remap_into] and returns immediately. The
+    /// buffers remain borrowed until the returned request has completed. The request is
+    /// registered with `scope`, see [ExchangeRequest].
+    pub fn remap_into_nonblocking<'b, T: Equivalence, S: Scope<'b>>(
+        &'b self,
+        scope: S,
+        data: &'b [T],
+        out_data: &'b mut [T],
+        chunk_size: usize,
+    ) -> ExchangeRequest<'b, T, S> {
+        assert_eq!(data.len(), chunk_size * self.number_of_source_indices);
+        assert_eq!(out_data.len(), chunk_size * self.number_of_target_indices);
+
+        let send_counts = chunked(&self.send_counts, chunk_size);
+        let send_displacements = chunked(&self.send_displacements, chunk_size);
+        let receive_counts = chunked(&self.receive_counts, chunk_size);
+        let receive_displacements = chunked(&self.receive_displacements, chunk_size);
+
+        unsafe {
+            let mut request = mpi_sys::RSMPI_REQUEST_NULL;
+            mpi_sys::MPI_Ialltoallv(
+                data.as_ptr() as *const c_void,
+                send_counts.as_ptr(),
+                send_displacements.as_ptr(),
+                <T as Equivalence>::equivalent_datatype().as_raw(),
+                out_data.as_mut_ptr() as *mut c_void,
+                receive_counts.as_ptr(),
+                receive_displacements.as_ptr(),
+                <T as Equivalence>::equivalent_datatype().as_raw(),
+                self.comm.as_raw(),
+                &mut request,
+            );
+            // The count vectors are moved into the request. Moving a vector does not move
+            // its heap allocation, so the pointers passed to MPI stay valid.
+            ExchangeRequest::from_raw(
+                vec![request],
+                vec![
+                    send_counts,
+                    send_displacements,
+                    receive_counts,
+                    receive_displacements,
+                ],
+                scope,
+            )
+        }
+    }
+}
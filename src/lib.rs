@@ -5,16 +5,45 @@
 #![warn(missing_docs)]
 
 pub mod array_tools;
+pub mod block_cyclic_layout;
+#[cfg(feature = "checked")]
+mod check;
+pub mod comm_ref;
 pub mod data_mapper;
+pub mod error;
+pub mod field_batch;
+pub mod general_index_layout;
 pub mod ghost_communicator;
+pub mod ghosted_vector;
 pub mod index_embedding;
 pub mod index_layout;
+pub mod indexed_exchange;
+pub mod layout;
+pub mod layout_mover;
+pub mod layout_remap;
 pub mod permutation;
+pub mod persistent_exchange;
+pub mod reduction;
+pub mod request;
 
 pub use array_tools::{
     all_to_allv, displacements, redistribute, scatterv, scatterv_root, sort_to_bins,
 };
-pub use data_mapper::Global2LocalDataMapper;
-pub use ghost_communicator::GhostCommunicator;
-pub use index_layout::IndexLayout;
+pub use block_cyclic_layout::BlockCyclicLayout;
+pub use comm_ref::CommRef;
+pub use data_mapper::{DataMapPlan, Global2LocalDataMapper};
+pub use error::Error;
+pub use field_batch::FieldBatch;
+pub use general_index_layout::GeneralIndexLayout;
+pub use ghost_communicator::{
+    GhostCommunicator, GhostCommunicatorBackend, GhostCommunicatorOptions, GhostCommunicatorSetup,
+};
+pub use ghosted_vector::GhostedVector;
+pub use index_layout::{BalancedLayout, IndexLayout};
+pub use indexed_exchange::IndexedExchange;
+pub use layout::Layout;
+pub use layout_mover::LayoutMover;
+pub use layout_remap::LayoutRemap;
 pub use permutation::DataPermutation;
+pub use persistent_exchange::PersistentExchange;
+pub use request::ExchangeRequest;
@@ -0,0 +1,7 @@
+// Constants of the MPI interface that are not provided by `mpi-sys`.
+//
+// MPI implementations define these constants as macros, which cannot be used from Rust.
+
+#include "mpi.h"
+
+const MPI_Datatype BEMPP_MPI_PACKED = MPI_PACKED;
@@ -8,122 +8,248 @@ use std::rc::Rc;
 use itertools::izip;
 use mpi::traits::{Communicator, Equivalence};
 
+use crate::error::{agree_collectively, check_size, Error};
+use crate::field_batch::FieldBatch;
 use crate::index_layout::IndexLayout;
+use crate::indexed_exchange::IndexedExchange;
+use crate::layout::Layout;
 
 /// Permuation of data.
-pub struct DataPermutation<'a, C: Communicator> {
-    index_layout: Rc<IndexLayout<'a, C>>,
+///
+/// The index layout can be any [Layout] and defaults to an [IndexLayout].
+pub struct DataPermutation<'a, C: Communicator, L: Layout<'a, Comm = C> = IndexLayout<'a, C>> {
+    index_layout: Rc<L>,
     nindices: usize,
-    my_rank: usize,
     custom_local_indices: Vec<usize>,
     local_to_custom_map: Vec<usize>,
     receive_to_custom_map: Vec<usize>,
-    ghost_communicator: crate::GhostCommunicator<usize>,
+    ghost_communicator: crate::GhostCommunicator<'a, usize, C>,
+    indexed_exchange: Option<IndexedExchange>,
 }
 
-impl<'a, C: Communicator> DataPermutation<'a, C> {
+impl<'a, C: Communicator, L: Layout<'a, Comm = C>> DataPermutation<'a, C, L> {
     /// Create a new permutation object.
-    pub fn new(index_layout: Rc<IndexLayout<'a, C>>, custom_indices: &[usize]) -> Self {
-        // We first need to identify which custom indices are local and which are global.
+    pub fn new(index_layout: Rc<L>, custom_indices: &[usize]) -> Self {
+        #[cfg(feature = "checked")]
+        crate::check::check_global_indices(index_layout.as_ref(), custom_indices);
 
-        let comm = index_layout.comm();
-        let my_rank = comm.rank() as usize;
+        // We first need to identify which custom indices are local and which are global.
 
         let mut custom_local_indices = Vec::new();
-        let mut custom_ghost_indices = Vec::new();
-        let mut ghost_ranks = Vec::new();
-
         let mut local_to_custom_map = Vec::<usize>::new();
-        let mut ghost_to_custom_map = Vec::<usize>::new();
 
         for (pos, &index) in custom_indices.iter().enumerate() {
-            let index_rank = index_layout.rank_from_index(index).unwrap();
-            if index_rank == my_rank {
-                custom_local_indices.push(index_layout.global2local(my_rank, index).unwrap());
+            if let Some(local_index) = index_layout.global2local_owned(index) {
+                custom_local_indices.push(local_index);
                 local_to_custom_map.push(pos);
-            } else {
-                custom_ghost_indices.push(index);
-                ghost_ranks.push(index_rank);
-                ghost_to_custom_map.push(pos);
             }
         }
 
         // We can now send up the ghost communicator for all indices that are not local.
 
         let ghost_communicator =
-            crate::GhostCommunicator::new(&custom_ghost_indices, &ghost_ranks, comm);
-
-        // We now need the map from the receive indices to our custom indices. For
-        // this we first compute the map from the receive indices to custom ghost indices
-        // and then map on to actual positions in the custom indices.
-
-        let ghost_permutation =
-            permutation_map(ghost_communicator.receive_indices(), &custom_ghost_indices);
+            crate::GhostCommunicator::from_layout(index_layout.as_ref(), custom_indices, true);
+
+        // Each owned index must be requested exactly once, either locally or by another
+        // process.
+
+        #[cfg(feature = "checked")]
+        crate::check::check_requested_once(
+            index_layout.as_ref(),
+            custom_local_indices.iter().copied().chain(
+                ghost_communicator
+                    .send_indices()
+                    .iter()
+                    .map(|&index| index_layout.global2local_owned(index).unwrap()),
+            ),
+        );
 
-        // We now use this to map from receive indices to the corresponding positions in the custom Arc::new(indices)
+        // We now need the map from the receive indices to the corresponding positions
+        // in the custom indices.
 
-        let mut receive_to_custom_map =
-            Vec::<usize>::with_capacity(ghost_communicator.total_receive_count());
+        let mut receive_to_custom_map = vec![0; ghost_communicator.total_receive_count()];
 
-        for index in 0..ghost_to_custom_map.len() {
-            receive_to_custom_map.push(ghost_to_custom_map[ghost_permutation[index]]);
+        for (pos, &index) in custom_indices.iter().enumerate() {
+            if let Some(receive_position) = ghost_communicator.receive_position(index) {
+                receive_to_custom_map[receive_position] = pos;
+            }
         }
 
         Self {
             index_layout,
             nindices: custom_indices.len(),
-            my_rank,
             custom_local_indices,
             local_to_custom_map,
             receive_to_custom_map,
             ghost_communicator,
+            indexed_exchange: None,
         }
     }
 
+    /// Return the inverse permutation.
+    ///
+    /// The index layout of the inverse is the custom index layout of this permutation, with
+    /// the custom indices numbered consecutively in the order of the ranks. The forward
+    /// permutation of the inverse moves data as the backward permutation of this permutation.
+    /// This is a collective operation.
+    pub fn inverse(&self) -> DataPermutation<'a, C> {
+        let custom_layout =
+            IndexLayout::from_local_counts(self.nindices, self.index_layout.comm_ref().clone());
+
+        // Each local index of the index layout is sent to the custom position that requested
+        // it. Sending the positions back gives the custom indices of the inverse.
+
+        let (first, last) = custom_layout.local_range();
+        let custom_positions = (first..last).collect::<Vec<_>>();
+        let mut inverse_indices = vec![0; self.index_layout.number_of_local_indices()];
+        self.backward_permute(&custom_positions, &mut inverse_indices, 1);
+
+        DataPermutation::new(Rc::new(custom_layout), &inverse_indices)
+    }
+
+    /// Return the permutation that applies this permutation followed by `other`.
+    ///
+    /// `other` must permute data given in the custom index layout of this permutation, i.e.
+    /// the `i`-th local index of its index layout is the `i`-th custom index of this
+    /// permutation on each process. The composed permutation moves data directly from the
+    /// index layout of this permutation to the custom index layout of `other`. This is a
+    /// collective operation.
+    ///
+    /// Panics if the layouts do not match. See [DataPermutation::try_compose] for a
+    /// non-panicking version.
+    pub fn compose<L2: Layout<'a, Comm = C>>(
+        &self,
+        other: &DataPermutation<'a, C, L2>,
+    ) -> DataPermutation<'a, C, L> {
+        self.try_compose(other)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Return the permutation that applies this permutation followed by `other`.
+    ///
+    /// Returns [Error::SizeMismatch] if the number of local indices of the index layout of
+    /// `other` differs from the number of custom indices of this permutation. The processes
+    /// agree on this check before any data is exchanged, so that all processes return
+    /// [Error::FailedOnOtherRanks] if it fails on another process.
+    pub fn try_compose<L2: Layout<'a, Comm = C>>(
+        &self,
+        other: &DataPermutation<'a, C, L2>,
+    ) -> Result<DataPermutation<'a, C, L>, Error> {
+        agree_collectively(
+            self.index_layout.comm(),
+            check_size(self.nindices, other.index_layout.number_of_local_indices()),
+        )?;
+
+        // Following the global indices through both permutations gives, for each custom
+        // index of `other`, the global index in the index layout of this permutation.
+
+        let global_indices = (0..self.index_layout.number_of_local_indices())
+            .map(|index| self.index_layout.local2global(index).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut custom_indices = vec![0; self.nindices];
+        self.try_forward_permute(&global_indices, &mut custom_indices, 1)?;
+
+        let mut composed_indices = vec![0; other.nindices];
+        other.try_forward_permute(&custom_indices, &mut composed_indices, 1)?;
+
+        Ok(DataPermutation::new(
+            self.index_layout.clone(),
+            &composed_indices,
+        ))
+    }
+
+    /// Use MPI derived datatypes for the data exchange.
+    ///
+    /// If enabled, the permutations send values directly from the input array and receive
+    /// them directly into the output array instead of going through intermediate buffers.
+    /// The datatypes are created on first use for each value type and chunk size.
+    pub fn use_derived_datatypes(&mut self, enable: bool) {
+        self.indexed_exchange = if enable {
+            let send_positions = self
+                .ghost_communicator
+                .send_indices()
+                .iter()
+                .map(|&index| self.index_layout.global2local_owned(index).unwrap())
+                .collect::<Vec<_>>();
+            Some(IndexedExchange::new(
+                &self.ghost_communicator,
+                &send_positions,
+                &self.receive_to_custom_map,
+            ))
+        } else {
+            None
+        };
+    }
+
     /// Permute data from the layout given by the `index_set` to the custom index layout.
+    ///
+    /// Panics if the arguments are invalid. See [DataPermutation::try_forward_permute] for a
+    /// non-panicking version.
     pub fn forward_permute<T: Equivalence + Copy + Default>(
         &self,
         data: &[T],
         permuted_data: &mut [T],
         chunk_size: usize,
     ) {
-        assert_eq!(
-            data.len(),
-            chunk_size * self.index_layout.number_of_local_indices()
-        );
-        assert_eq!(permuted_data.len(), chunk_size * self.nindices);
+        self.try_forward_permute(data, permuted_data, chunk_size)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Permute data from the layout given by the `index_set` to the custom index layout.
+    ///
+    /// Returns [Error::SizeMismatch] if `data` or `permuted_data` do not have the required
+    /// lengths. The processes agree on the result of the checks before any data is exchanged,
+    /// so that all processes return [Error::FailedOnOtherRanks] if the arguments are invalid on
+    /// another process. Returns [Error::Mpi] if MPI reports an error during the exchange.
+    pub fn try_forward_permute<T: Equivalence + Copy + Default>(
+        &self,
+        data: &[T],
+        permuted_data: &mut [T],
+        chunk_size: usize,
+    ) -> Result<(), Error> {
+        agree_collectively(
+            self.index_layout.comm(),
+            check_size(
+                chunk_size * self.index_layout.number_of_local_indices(),
+                data.len(),
+            )
+            .and(check_size(chunk_size * self.nindices, permuted_data.len())),
+        )?;
+
+        // With derived datatypes the ghost data is received directly into the permuted data.
+
+        if let Some(indexed_exchange) = &self.indexed_exchange {
+            indexed_exchange.try_forward_send_values_by_chunks(
+                &self.ghost_communicator,
+                data,
+                permuted_data,
+                chunk_size,
+            )?;
+            self.copy_local_forward(data, permuted_data, chunk_size);
+            return Ok(());
+        }
 
         // We first need to get the send data. This is quite easy. We can just
         // use the global2local method from the index layout.
 
-        let mut send_data =
-            Vec::<T>::with_capacity(chunk_size * self.ghost_communicator.total_send_count());
-
-        for &index in self.ghost_communicator.send_indices() {
-            let local_start_index =
-                chunk_size * self.index_layout.global2local(self.my_rank, index).unwrap();
-            let local_end_index = local_start_index + chunk_size;
-            send_data.extend_from_slice(&data[local_start_index..local_end_index]);
-        }
+        let send_data = self.pack_send_data(data, chunk_size);
 
         // Now we do the data exchange across ranks.
 
         let mut received_data =
             vec![T::default(); chunk_size * self.ghost_communicator.total_receive_count()];
-        self.ghost_communicator.forward_send_values_by_chunks(
+        self.ghost_communicator.forward_send_values_with_counts(
             &send_data,
             &mut received_data,
-            chunk_size,
-        );
+            &mut self.ghost_communicator.chunked_counts(chunk_size),
+        )?;
 
         // The data exchange is done. Now we have to fit everything back together to get to our custom data layout.
 
         // First we iterate through the local data.
 
-        for (&pos, &local_index) in izip!(&self.local_to_custom_map, &self.custom_local_indices) {
-            permuted_data[chunk_size * pos..chunk_size * (1 + pos)]
-                .copy_from_slice(&data[chunk_size * local_index..chunk_size * (1 + local_index)]);
-        }
+        self.copy_local_forward(data, permuted_data, chunk_size);
 
         // Now we iterate through the ghost data and assign it to the right position in the permuted data.
 
@@ -134,20 +260,56 @@ impl<'a, C: Communicator> DataPermutation<'a, C> {
             permuted_data[chunk_size * permuted_index..chunk_size * (1 + permuted_index)]
                 .copy_from_slice(chunk);
         }
+
+        Ok(())
     }
 
     /// Permute data from the custom index layout to the layout given by the `index_set`.
+    ///
+    /// Panics if the arguments are invalid. See [DataPermutation::try_backward_permute] for a
+    /// non-panicking version.
     pub fn backward_permute<T: Equivalence + Copy + Default>(
         &self,
         data: &[T],
         permuted_data: &mut [T],
         chunk_size: usize,
     ) {
-        assert_eq!(data.len(), chunk_size * self.nindices);
-        assert_eq!(
-            permuted_data.len(),
-            chunk_size * self.index_layout.number_of_local_indices()
-        );
+        self.try_backward_permute(data, permuted_data, chunk_size)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Permute data from the custom index layout to the layout given by the `index_set`.
+    ///
+    /// Returns [Error::SizeMismatch] if `data` or `permuted_data` do not have the required
+    /// lengths. The processes agree on the result of the checks before any data is exchanged,
+    /// so that all processes return [Error::FailedOnOtherRanks] if the arguments are invalid on
+    /// another process. Returns [Error::Mpi] if MPI reports an error during the exchange.
+    pub fn try_backward_permute<T: Equivalence + Copy + Default>(
+        &self,
+        data: &[T],
+        permuted_data: &mut [T],
+        chunk_size: usize,
+    ) -> Result<(), Error> {
+        agree_collectively(
+            self.index_layout.comm(),
+            check_size(chunk_size * self.nindices, data.len()).and(check_size(
+                chunk_size * self.index_layout.number_of_local_indices(),
+                permuted_data.len(),
+            )),
+        )?;
+
+        // With derived datatypes the ghost data is sent back directly from the custom data.
+
+        if let Some(indexed_exchange) = &self.indexed_exchange {
+            indexed_exchange.try_backward_send_values_by_chunks(
+                &self.ghost_communicator,
+                data,
+                permuted_data,
+                chunk_size,
+            )?;
+            self.copy_local_backward(data, permuted_data, chunk_size);
+            return Ok(());
+        }
 
         // We need to fill up the receive indices as this is the data that is sent around.
         let mut receive_data =
@@ -164,11 +326,11 @@ impl<'a, C: Communicator> DataPermutation<'a, C> {
             vec![T::default(); chunk_size * self.ghost_communicator.total_send_count()];
 
         // We now send data backwards from receiver to sender.
-        self.ghost_communicator.backward_send_values_by_chunks(
+        self.ghost_communicator.backward_send_values_with_counts(
             &receive_data,
             &mut send_data,
-            chunk_size,
-        );
+            &mut self.ghost_communicator.chunked_counts(chunk_size),
+        )?;
 
         // We now go through the send indices and fill the output data with the corresponding values.
 
@@ -177,12 +339,328 @@ impl<'a, C: Communicator> DataPermutation<'a, C> {
             send_data.chunks(chunk_size)
         ) {
             let local_start_index =
-                chunk_size * self.index_layout.global2local(self.my_rank, index).unwrap();
+                chunk_size * self.index_layout.global2local_owned(index).unwrap();
             let local_end_index = local_start_index + chunk_size;
             permuted_data[local_start_index..local_end_index].copy_from_slice(chunk);
         }
 
         // We still have to handle the indices that lived only locally.
+        self.copy_local_backward(data, permuted_data, chunk_size);
+
+        Ok(())
+    }
+
+    /// Permute data in place from the layout given by the `index_set` to the custom index layout.
+    ///
+    /// This requires that the number of custom indices on the current process is the number of
+    /// local indices of the index layout. Only the data sent to other processes is buffered.
+    pub fn forward_permute_in_place<T: Equivalence + Copy + Default>(
+        &self,
+        data: &mut [T],
+        chunk_size: usize,
+    ) {
+        assert_eq!(
+            self.nindices,
+            self.index_layout.number_of_local_indices(),
+            "In-place permutations require as many custom indices as local indices."
+        );
+        assert_eq!(data.len(), chunk_size * self.nindices);
+
+        let send_data = self.pack_send_data(data, chunk_size);
+
+        let mut received_data =
+            vec![T::default(); chunk_size * self.ghost_communicator.total_receive_count()];
+        self.ghost_communicator.forward_send_values_by_chunks(
+            &send_data,
+            &mut received_data,
+            chunk_size,
+        );
+
+        // The sent data has been copied out, so the local data can be moved over it.
+
+        move_chunks_in_place(
+            data,
+            chunk_size,
+            &self.custom_local_indices,
+            &self.local_to_custom_map,
+        );
+
+        for (&permuted_index, chunk) in izip!(
+            &self.receive_to_custom_map,
+            received_data.chunks(chunk_size)
+        ) {
+            data[chunk_size * permuted_index..chunk_size * (1 + permuted_index)]
+                .copy_from_slice(chunk);
+        }
+    }
+
+    /// Permute data in place from the custom index layout to the layout given by the `index_set`.
+    ///
+    /// This requires that the number of custom indices on the current process is the number of
+    /// local indices of the index layout. Only the data sent to other processes is buffered.
+    pub fn backward_permute_in_place<T: Equivalence + Copy + Default>(
+        &self,
+        data: &mut [T],
+        chunk_size: usize,
+    ) {
+        assert_eq!(
+            self.nindices,
+            self.index_layout.number_of_local_indices(),
+            "In-place permutations require as many custom indices as local indices."
+        );
+        assert_eq!(data.len(), chunk_size * self.nindices);
+
+        let mut receive_data =
+            Vec::<T>::with_capacity(chunk_size * self.ghost_communicator.total_receive_count());
+        for &custom_index in &self.receive_to_custom_map {
+            receive_data.extend_from_slice(
+                &data[custom_index * chunk_size..(1 + custom_index) * chunk_size],
+            )
+        }
+
+        let mut send_data =
+            vec![T::default(); chunk_size * self.ghost_communicator.total_send_count()];
+        self.ghost_communicator.backward_send_values_by_chunks(
+            &receive_data,
+            &mut send_data,
+            chunk_size,
+        );
+
+        move_chunks_in_place(
+            data,
+            chunk_size,
+            &self.local_to_custom_map,
+            &self.custom_local_indices,
+        );
+
+        for (&index, chunk) in izip!(
+            self.ghost_communicator.send_indices(),
+            send_data.chunks(chunk_size)
+        ) {
+            let local_start_index =
+                chunk_size * self.index_layout.global2local_owned(index).unwrap();
+            data[local_start_index..local_start_index + chunk_size].copy_from_slice(chunk);
+        }
+    }
+
+    /// Permute data with a variable number of values per index from the layout given by the
+    /// `index_set` to the custom index layout.
+    ///
+    /// The values of the `i`-th local index are `data[offsets[i]..offsets[i + 1]]`, so
+    /// `offsets` has one more entry than there are local indices. Returns the offsets and the
+    /// values in the custom index layout in the same format. The numbers of values per index
+    /// are exchanged first, followed by the values themselves.
+    pub fn forward_permute_ragged<T: Equivalence + Copy + Default>(
+        &self,
+        data: &[T],
+        offsets: &[usize],
+    ) -> (Vec<usize>, Vec<T>) {
+        let lengths = lengths_from_offsets(offsets, data.len());
+        assert_eq!(lengths.len(), self.index_layout.number_of_local_indices());
+
+        let mut permuted_lengths = vec![0; self.nindices];
+        self.forward_permute(&lengths, &mut permuted_lengths, 1);
+        let permuted_offsets = offsets_from_lengths(&permuted_lengths);
+
+        let mut send_lengths = Vec::with_capacity(self.ghost_communicator.total_send_count());
+        let mut send_data = Vec::<T>::new();
+        for &index in self.ghost_communicator.send_indices() {
+            let local_index = self.index_layout.global2local_owned(index).unwrap();
+            send_lengths.push(lengths[local_index]);
+            send_data.extend_from_slice(&data[offsets[local_index]..offsets[1 + local_index]]);
+        }
+
+        let receive_lengths = self
+            .receive_to_custom_map
+            .iter()
+            .map(|&pos| permuted_lengths[pos])
+            .collect::<Vec<_>>();
+        let mut received_data = vec![T::default(); receive_lengths.iter().sum()];
+        self.ghost_communicator.forward_send_ragged_values(
+            &send_data,
+            &send_lengths,
+            &mut received_data,
+            &receive_lengths,
+        );
+
+        let mut permuted_data = vec![T::default(); *permuted_offsets.last().unwrap()];
+
+        for (&pos, &local_index) in izip!(&self.local_to_custom_map, &self.custom_local_indices) {
+            permuted_data[permuted_offsets[pos]..permuted_offsets[1 + pos]]
+                .copy_from_slice(&data[offsets[local_index]..offsets[1 + local_index]]);
+        }
+
+        let mut start = 0;
+        for &pos in &self.receive_to_custom_map {
+            let end = start + permuted_lengths[pos];
+            permuted_data[permuted_offsets[pos]..permuted_offsets[1 + pos]]
+                .copy_from_slice(&received_data[start..end]);
+            start = end;
+        }
+
+        (permuted_offsets, permuted_data)
+    }
+
+    /// Permute data with a variable number of values per index from the custom index layout to
+    /// the layout given by the `index_set`.
+    ///
+    /// The values of the `i`-th custom index are `data[offsets[i]..offsets[i + 1]]`. Returns the
+    /// offsets and the values in the layout given by the `index_set` in the same format.
+    pub fn backward_permute_ragged<T: Equivalence + Copy + Default>(
+        &self,
+        data: &[T],
+        offsets: &[usize],
+    ) -> (Vec<usize>, Vec<T>) {
+        let lengths = lengths_from_offsets(offsets, data.len());
+        assert_eq!(lengths.len(), self.nindices);
+
+        let mut permuted_lengths = vec![0; self.index_layout.number_of_local_indices()];
+        self.backward_permute(&lengths, &mut permuted_lengths, 1);
+        let permuted_offsets = offsets_from_lengths(&permuted_lengths);
+
+        let mut receive_lengths = Vec::with_capacity(self.ghost_communicator.total_receive_count());
+        let mut receive_data = Vec::<T>::new();
+        for &pos in &self.receive_to_custom_map {
+            receive_lengths.push(lengths[pos]);
+            receive_data.extend_from_slice(&data[offsets[pos]..offsets[1 + pos]]);
+        }
+
+        let send_local_indices = self
+            .ghost_communicator
+            .send_indices()
+            .iter()
+            .map(|&index| self.index_layout.global2local_owned(index).unwrap())
+            .collect::<Vec<_>>();
+        let send_lengths = send_local_indices
+            .iter()
+            .map(|&local_index| permuted_lengths[local_index])
+            .collect::<Vec<_>>();
+        let mut send_data = vec![T::default(); send_lengths.iter().sum()];
+        self.ghost_communicator.backward_send_ragged_values(
+            &receive_data,
+            &receive_lengths,
+            &mut send_data,
+            &send_lengths,
+        );
+
+        let mut permuted_data = vec![T::default(); *permuted_offsets.last().unwrap()];
+
+        for (&pos, &local_index) in izip!(&self.local_to_custom_map, &self.custom_local_indices) {
+            permuted_data[permuted_offsets[local_index]..permuted_offsets[1 + local_index]]
+                .copy_from_slice(&data[offsets[pos]..offsets[1 + pos]]);
+        }
+
+        let mut start = 0;
+        for &local_index in &send_local_indices {
+            let end = start + permuted_lengths[local_index];
+            permuted_data[permuted_offsets[local_index]..permuted_offsets[1 + local_index]]
+                .copy_from_slice(&send_data[start..end]);
+            start = end;
+        }
+
+        (permuted_offsets, permuted_data)
+    }
+
+    /// Permute several fields at once from the layout given by the `index_set` to the custom
+    /// index layout.
+    ///
+    /// Each field of the batch is permuted as in [DataPermutation::forward_permute]. The values
+    /// of all fields are exchanged together with a single message per neighbour. The batch is
+    /// consumed, which releases the borrowed outputs.
+    pub fn forward_permute_batch(&self, mut batch: FieldBatch<'_>) {
+        batch.assert_counts(self.index_layout.number_of_local_indices(), self.nindices);
+        if batch.is_empty() {
+            return;
+        }
+
+        let layout = batch.record_layout(self.ghost_communicator.comm());
+        let record_size = layout.record_size();
+
+        let send_records = batch.pack(
+            &layout,
+            self.ghost_communicator
+                .send_indices()
+                .iter()
+                .map(|&index| self.index_layout.global2local_owned(index).unwrap()),
+        );
+        let mut receive_records = layout.records(self.ghost_communicator.total_receive_count());
+        self.ghost_communicator.forward_send_values_by_chunks(
+            &send_records,
+            &mut receive_records,
+            record_size,
+        );
+
+        for (&pos, &local_index) in izip!(&self.local_to_custom_map, &self.custom_local_indices) {
+            batch.copy(local_index, pos);
+        }
+        batch.unpack(
+            &layout,
+            &receive_records,
+            self.receive_to_custom_map.iter().copied(),
+        );
+    }
+
+    /// Permute several fields at once from the custom index layout to the layout given by the
+    /// `index_set`.
+    ///
+    /// Each field of the batch is permuted as in [DataPermutation::backward_permute]. The values
+    /// of all fields are exchanged together with a single message per neighbour. The batch is
+    /// consumed, which releases the borrowed outputs.
+    pub fn backward_permute_batch(&self, mut batch: FieldBatch<'_>) {
+        batch.assert_counts(self.nindices, self.index_layout.number_of_local_indices());
+        if batch.is_empty() {
+            return;
+        }
+
+        let layout = batch.record_layout(self.ghost_communicator.comm());
+        let record_size = layout.record_size();
+
+        let receive_records = batch.pack(&layout, self.receive_to_custom_map.iter().copied());
+        let mut send_records = layout.records(self.ghost_communicator.total_send_count());
+        self.ghost_communicator.backward_send_values_by_chunks(
+            &receive_records,
+            &mut send_records,
+            record_size,
+        );
+
+        batch.unpack(
+            &layout,
+            &send_records,
+            self.ghost_communicator
+                .send_indices()
+                .iter()
+                .map(|&index| self.index_layout.global2local_owned(index).unwrap()),
+        );
+        for (&pos, &local_index) in izip!(&self.local_to_custom_map, &self.custom_local_indices) {
+            batch.copy(pos, local_index);
+        }
+    }
+
+    /// Collect the data of the send indices.
+    fn pack_send_data<T: Copy>(&self, data: &[T], chunk_size: usize) -> Vec<T> {
+        let mut send_data =
+            Vec::<T>::with_capacity(chunk_size * self.ghost_communicator.total_send_count());
+
+        for &index in self.ghost_communicator.send_indices() {
+            let local_start_index =
+                chunk_size * self.index_layout.global2local_owned(index).unwrap();
+            let local_end_index = local_start_index + chunk_size;
+            send_data.extend_from_slice(&data[local_start_index..local_end_index]);
+        }
+
+        send_data
+    }
+
+    /// Copy the locally owned data into the custom layout.
+    fn copy_local_forward<T: Copy>(&self, data: &[T], permuted_data: &mut [T], chunk_size: usize) {
+        for (&pos, &local_index) in izip!(&self.local_to_custom_map, &self.custom_local_indices) {
+            permuted_data[chunk_size * pos..chunk_size * (1 + pos)]
+                .copy_from_slice(&data[chunk_size * local_index..chunk_size * (1 + local_index)]);
+        }
+    }
+
+    /// Copy the locally owned data from the custom layout back into the index layout.
+    fn copy_local_backward<T: Copy>(&self, data: &[T], permuted_data: &mut [T], chunk_size: usize) {
         for (&pos, &local_index) in izip!(&self.local_to_custom_map, &self.custom_local_indices) {
             permuted_data[local_index * chunk_size..(1 + local_index) * chunk_size]
                 .copy_from_slice(&data[pos * chunk_size..(1 + pos) * chunk_size]);
@@ -227,6 +705,104 @@ pub fn concatenate_permutations(perm1: &[usize], perm2: &[usize]) -> Vec<usize>
     result
 }
 
+/// Return the number of values per index from CSR offsets.
+fn lengths_from_offsets(offsets: &[usize], nvalues: usize) -> Vec<usize> {
+    assert!(
+        !offsets.is_empty(),
+        "Offsets must contain at least one entry."
+    );
+    assert_eq!(offsets[0], 0, "Offsets must start with zero.");
+    assert_eq!(
+        *offsets.last().unwrap(),
+        nvalues,
+        "The last offset must be the number of values."
+    );
+    offsets
+        .windows(2)
+        .map(|window| {
+            assert!(window[0] <= window[1], "Offsets must be non-decreasing.");
+            window[1] - window[0]
+        })
+        .collect()
+}
+
+/// Return CSR offsets from the number of values per index.
+fn offsets_from_lengths(lengths: &[usize]) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(lengths.iter().scan(0, |acc, &length| {
+            *acc += length;
+            Some(*acc)
+        }))
+        .collect()
+}
+
+/// Move the chunk at position `sources[i]` to position `targets[i]` in place.
+///
+/// The positions in `sources` and in `targets` must be unique. Chunks at positions that are
+/// targets but not sources are overwritten. The moves form chains that start at a source that
+/// is not a target, and cycles. Chains are followed backwards from their end, so that each
+/// chunk is moved before it is overwritten. Cycles need a single temporary chunk.
+fn move_chunks_in_place<T: Copy>(
+    data: &mut [T],
+    chunk_size: usize,
+    sources: &[usize],
+    targets: &[usize],
+) {
+    assert_eq!(sources.len(), targets.len());
+    if chunk_size == 0 {
+        return;
+    }
+
+    let npositions = data.len() / chunk_size;
+
+    let mut source_of = vec![None; npositions];
+    let mut is_source = vec![false; npositions];
+    let mut moved = vec![false; npositions];
+
+    for (&source, &target) in izip!(sources, targets) {
+        source_of[target] = Some(source);
+        is_source[source] = true;
+    }
+
+    let move_chunk = |data: &mut [T], from: usize, to: usize| {
+        data.copy_within(chunk_size * from..chunk_size * (1 + from), chunk_size * to);
+    };
+
+    // Follow the chains backwards from the targets whose chunks are not needed.
+
+    for &end in targets {
+        if is_source[end] {
+            continue;
+        }
+        let mut current = end;
+        while let Some(source) = source_of[current] {
+            move_chunk(data, source, current);
+            moved[current] = true;
+            current = source;
+        }
+    }
+
+    // All remaining moves form cycles.
+
+    for &start in targets {
+        if moved[start] {
+            continue;
+        }
+        let temp = data[chunk_size * start..chunk_size * (1 + start)].to_vec();
+        let mut current = start;
+        loop {
+            moved[current] = true;
+            let source = source_of[current].unwrap();
+            if source == start {
+                data[chunk_size * current..chunk_size * (1 + current)].copy_from_slice(&temp);
+                break;
+            }
+            move_chunk(data, source, current);
+            current = source;
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
 
@@ -244,4 +820,20 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn test_move_chunks_in_place() {
+        // A cycle 0 -> 2 -> 4 -> 0, a chain 1 -> 3 -> 5 and position 1 is left untouched.
+        let mut data = vec![0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5];
+        super::move_chunks_in_place(&mut data, 2, &[0, 2, 4, 1, 3], &[2, 4, 0, 3, 5]);
+
+        assert_eq!(data, [4, 4, 1, 1, 0, 0, 1, 1, 2, 2, 3, 3]);
+    }
+
+    #[test]
+    fn test_offsets() {
+        let lengths = super::lengths_from_offsets(&[0, 2, 2, 5], 5);
+        assert_eq!(lengths, [2, 0, 3]);
+        assert_eq!(super::offsets_from_lengths(&lengths), [0, 2, 2, 5]);
+    }
 }
@@ -0,0 +1,289 @@
+//! Persistent ghost exchanges.
+//!
+//! Time stepping loops exchange the same ghost pattern with the same buffers many times.
+//! A [PersistentExchange] fixes the buffers and the communication pattern of a
+//! [GhostCommunicator] once, so that every further exchange only needs a call to
+//! [PersistentExchange::start] and [PersistentExchange::wait].
+//!
+//! If the MPI library implements MPI-4, the exchange is set up as a persistent neighbourhood
+//! collective via `MPI_Neighbor_alltoallv_init`. Otherwise each call to
+//! [PersistentExchange::start] re-posts a `MPI_Ineighbor_alltoallv` with the precomputed
+//! counts and displacements. Ghost communicators with the point-to-point backend always use
+//! persistent point-to-point requests.
+//!
+//! The MPI version is detected from `mpi.h` by the build script, which sets the `mpi4` cfg.
+//!
+//! MPI may access the buffers of an exchange as long as its requests exist. As for the
+//! non-blocking exchanges in [request](crate::request), each exchange is therefore registered
+//! with a [Scope] whose buffers outlive it. A leaked exchange aborts the program at the end of
+//! its scope.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+
+use itertools::izip;
+
+use mpi::request::{Scope, StaticScope};
+use mpi::topology::SimpleCommunicator;
+use mpi::traits::{AsRaw, Communicator, Equivalence};
+
+use crate::ghost_communicator::chunked;
+use crate::{GhostCommunicator, GhostCommunicatorBackend};
+
+/// A ghost exchange with fixed buffers that can be repeated.
+pub struct PersistentExchange<'a, T: Equivalence, S: Scope<'a> = StaticScope> {
+    send_buffer: &'a mut [T],
+    receive_buffer: &'a mut [T],
+    target_ranks: &'a [i32],
+    send_counts: Vec<i32>,
+    send_displacements: Vec<i32>,
+    source_ranks: &'a [i32],
+    receive_counts: Vec<i32>,
+    receive_displacements: Vec<i32>,
+    comm: &'a SimpleCommunicator,
+    backend: GhostCommunicatorBackend,
+    requests: Vec<mpi_sys::MPI_Request>,
+    active: bool,
+    scope: S,
+    _lifetime: PhantomData<Cell<&'a ()>>,
+}
+
+impl<'a, T: Equivalence, S: Scope<'a>> PersistentExchange<'a, T, S> {
+    /// Create a persistent forward exchange.
+    ///
+    /// Each exchange sends the values in `out_values` to the ghosts in `in_values`, as in
+    /// [GhostCommunicator::forward_send_values_by_chunks]. The exchange is registered with
+    /// `scope`.
+    pub fn forward<I: Default + Copy + Equivalence, C: Communicator>(
+        scope: S,
+        ghost_communicator: &'a GhostCommunicator<'_, I, C>,
+        out_values: &'a mut [T],
+        in_values: &'a mut [T],
+        chunk_size: usize,
+    ) -> Self {
+        assert_eq!(
+            in_values.len(),
+            ghost_communicator.total_receive_count() * chunk_size
+        );
+        assert_eq!(
+            out_values.len(),
+            ghost_communicator.total_send_count() * chunk_size
+        );
+
+        let mut exchange = Self {
+            send_buffer: out_values,
+            receive_buffer: in_values,
+            target_ranks: ghost_communicator.out_ranks(),
+            send_counts: chunked(ghost_communicator.send_counts(), chunk_size),
+            send_displacements: chunked(ghost_communicator.send_displacements(), chunk_size),
+            source_ranks: ghost_communicator.in_ranks(),
+            receive_counts: chunked(ghost_communicator.receive_counts(), chunk_size),
+            receive_displacements: chunked(ghost_communicator.receive_displacements(), chunk_size),
+            comm: ghost_communicator.forward_comm(),
+            backend: ghost_communicator.backend(),
+            requests: Vec::new(),
+            active: false,
+            scope,
+            _lifetime: PhantomData,
+        };
+        exchange.scope.register();
+        exchange.init();
+        exchange
+    }
+
+    /// Create a persistent backward exchange.
+    ///
+    /// Each exchange sends the ghost values in `out_values` back to the owning processes,
+    /// as in [GhostCommunicator::backward_send_values_by_chunks]. The exchange is registered
+    /// with `scope`.
+    pub fn backward<I: Default + Copy + Equivalence, C: Communicator>(
+        scope: S,
+        ghost_communicator: &'a GhostCommunicator<'_, I, C>,
+        out_values: &'a mut [T],
+        in_values: &'a mut [T],
+        chunk_size: usize,
+    ) -> Self {
+        assert_eq!(
+            out_values.len(),
+            ghost_communicator.total_receive_count() * chunk_size
+        );
+        assert_eq!(
+            in_values.len(),
+            ghost_communicator.total_send_count() * chunk_size
+        );
+
+        let mut exchange = Self {
+            send_buffer: out_values,
+            receive_buffer: in_values,
+            target_ranks: ghost_communicator.in_ranks(),
+            send_counts: chunked(ghost_communicator.receive_counts(), chunk_size),
+            send_displacements: chunked(ghost_communicator.receive_displacements(), chunk_size),
+            source_ranks: ghost_communicator.out_ranks(),
+            receive_counts: chunked(ghost_communicator.send_counts(), chunk_size),
+            receive_displacements: chunked(ghost_communicator.send_displacements(), chunk_size),
+            comm: ghost_communicator.backward_comm(),
+            backend: ghost_communicator.backend(),
+            requests: Vec::new(),
+            active: false,
+            scope,
+            _lifetime: PhantomData,
+        };
+        exchange.scope.register();
+        exchange.init();
+        exchange
+    }
+
+    /// Return true if the requests are persistent and only need to be started.
+    fn is_persistent(&self) -> bool {
+        self.backend == GhostCommunicatorBackend::PointToPoint || cfg!(mpi4)
+    }
+
+    /// Initialise the persistent requests.
+    fn init(&mut self) {
+        match self.backend {
+            GhostCommunicatorBackend::PointToPoint => unsafe {
+                for (&source, &count, &displacement) in izip!(
+                    self.source_ranks,
+                    &self.receive_counts,
+                    &self.receive_displacements
+                ) {
+                    let mut request = mpi_sys::RSMPI_REQUEST_NULL;
+                    mpi_sys::MPI_Recv_init(
+                        self.receive_buffer.as_mut_ptr().add(displacement as usize) as *mut c_void,
+                        count,
+                        <T as Equivalence>::equivalent_datatype().as_raw(),
+                        source,
+                        0,
+                        self.comm.as_raw(),
+                        &mut request,
+                    );
+                    self.requests.push(request);
+                }
+                for (&target, &count, &displacement) in izip!(
+                    self.target_ranks,
+                    &self.send_counts,
+                    &self.send_displacements
+                ) {
+                    let mut request = mpi_sys::RSMPI_REQUEST_NULL;
+                    mpi_sys::MPI_Send_init(
+                        self.send_buffer.as_ptr().add(displacement as usize) as *const c_void,
+                        count,
+                        <T as Equivalence>::equivalent_datatype().as_raw(),
+                        target,
+                        0,
+                        self.comm.as_raw(),
+                        &mut request,
+                    );
+                    self.requests.push(request);
+                }
+            },
+            // With MPI-4 the exchange is initialised once here and only started afterwards.
+            #[cfg(mpi4)]
+            GhostCommunicatorBackend::NeighbourhoodCollective => unsafe {
+                let mut request = mpi_sys::RSMPI_REQUEST_NULL;
+                mpi_sys::MPI_Neighbor_alltoallv_init(
+                    self.send_buffer.as_ptr() as *const c_void,
+                    self.send_counts.as_ptr(),
+                    self.send_displacements.as_ptr(),
+                    <T as Equivalence>::equivalent_datatype().as_raw(),
+                    self.receive_buffer.as_mut_ptr() as *mut c_void,
+                    self.receive_counts.as_ptr(),
+                    self.receive_displacements.as_ptr(),
+                    <T as Equivalence>::equivalent_datatype().as_raw(),
+                    self.comm.as_raw(),
+                    mpi_sys::RSMPI_INFO_NULL,
+                    &mut request,
+                );
+                self.requests.push(request);
+            },
+            // Without MPI-4 the exchange is posted anew on every start.
+            #[cfg(not(mpi4))]
+            GhostCommunicatorBackend::NeighbourhoodCollective => {}
+        }
+    }
+
+    /// Start the exchange.
+    ///
+    /// The buffers must not be accessed until [PersistentExchange::wait] has been called.
+    pub fn start(&mut self) {
+        assert!(!self.active, "Exchange has already been started.");
+
+        if self.is_persistent() {
+            unsafe {
+                mpi_sys::MPI_Startall(self.requests.len() as i32, self.requests.as_mut_ptr());
+            }
+        } else {
+            unsafe {
+                let mut request = mpi_sys::RSMPI_REQUEST_NULL;
+                mpi_sys::MPI_Ineighbor_alltoallv(
+                    self.send_buffer.as_ptr() as *const c_void,
+                    self.send_counts.as_ptr(),
+                    self.send_displacements.as_ptr(),
+                    <T as Equivalence>::equivalent_datatype().as_raw(),
+                    self.receive_buffer.as_mut_ptr() as *mut c_void,
+                    self.receive_counts.as_ptr(),
+                    self.receive_displacements.as_ptr(),
+                    <T as Equivalence>::equivalent_datatype().as_raw(),
+                    self.comm.as_raw(),
+                    &mut request,
+                );
+                self.requests = vec![request];
+            }
+        }
+
+        self.active = true;
+    }
+
+    /// Wait for a started exchange to complete.
+    ///
+    /// Does nothing if the exchange is not active.
+    pub fn wait(&mut self) {
+        if self.active {
+            unsafe {
+                mpi_sys::MPI_Waitall(
+                    self.requests.len() as i32,
+                    self.requests.as_mut_ptr(),
+                    mpi_sys::RSMPI_STATUSES_IGNORE,
+                );
+            }
+            self.active = false;
+        }
+    }
+
+    /// Return true if the exchange has been started and not yet waited for.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Return the send buffer.
+    ///
+    /// New values for the next exchange can be written here while the exchange is not active.
+    pub fn send_buffer_mut(&mut self) -> &mut [T] {
+        assert!(!self.active, "Cannot access buffers of an active exchange.");
+        self.send_buffer
+    }
+
+    /// Return the receive buffer.
+    pub fn receive_buffer(&self) -> &[T] {
+        assert!(!self.active, "Cannot access buffers of an active exchange.");
+        self.receive_buffer
+    }
+}
+
+impl<'a, T: Equivalence, S: Scope<'a>> Drop for PersistentExchange<'a, T, S> {
+    fn drop(&mut self) {
+        self.wait();
+
+        // Persistent requests stay allocated after completion and must be freed explicitly.
+        if self.is_persistent() {
+            for request in self.requests.iter_mut() {
+                unsafe {
+                    mpi_sys::MPI_Request_free(request);
+                }
+            }
+        }
+
+        unsafe { self.scope.unregister() };
+    }
+}
@@ -0,0 +1,85 @@
+//! Reduction operators
+//!
+//! Reduction operators combine contributions for the same index, for example when ghost
+//! values are accumulated back onto their owning process. Besides the predefined [Sum],
+//! [Min] and [Max] operators any closure `Fn(&mut T, &T)` can be used as reduction operator.
+
+/// A reduction operator.
+pub trait ReductionOp<T> {
+    /// Combine `value` into `accumulator`.
+    fn combine(&self, accumulator: &mut T, value: &T);
+}
+
+/// Sum up all contributions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sum;
+
+/// Take the minimum of all contributions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Min;
+
+/// Take the maximum of all contributions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Max;
+
+impl<T: Copy + std::ops::AddAssign> ReductionOp<T> for Sum {
+    fn combine(&self, accumulator: &mut T, value: &T) {
+        *accumulator += *value;
+    }
+}
+
+impl<T: Copy + PartialOrd> ReductionOp<T> for Min {
+    fn combine(&self, accumulator: &mut T, value: &T) {
+        if *value < *accumulator {
+            *accumulator = *value;
+        }
+    }
+}
+
+impl<T: Copy + PartialOrd> ReductionOp<T> for Max {
+    fn combine(&self, accumulator: &mut T, value: &T) {
+        if *value > *accumulator {
+            *accumulator = *value;
+        }
+    }
+}
+
+impl<T, F: Fn(&mut T, &T)> ReductionOp<T> for F {
+    fn combine(&self, accumulator: &mut T, value: &T) {
+        self(accumulator, value)
+    }
+}
+
+/// Combine a slice of values elementwise into a slice of accumulators.
+pub(crate) fn combine_slices<T, Op: ReductionOp<T>>(op: &Op, accumulators: &mut [T], values: &[T]) {
+    assert_eq!(accumulators.len(), values.len());
+    for (accumulator, value) in accumulators.iter_mut().zip(values) {
+        op.combine(accumulator, value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{combine_slices, Max, Min, Sum};
+
+    #[test]
+    fn test_reduction_ops() {
+        let values = [3, -1, 7];
+
+        let mut sum = [1, 1, 1];
+        combine_slices(&Sum, &mut sum, &values);
+        assert_eq!(sum, [4, 0, 8]);
+
+        let mut min = [1, 1, 1];
+        combine_slices(&Min, &mut min, &values);
+        assert_eq!(min, [1, -1, 1]);
+
+        let mut max = [1, 1, 1];
+        combine_slices(&Max, &mut max, &values);
+        assert_eq!(max, [3, 1, 7]);
+
+        let mut product = [2, 2, 2];
+        combine_slices(&|a: &mut i32, b: &i32| *a *= *b, &mut product, &values);
+        assert_eq!(product, [6, -2, 14]);
+    }
+}
@@ -0,0 +1,92 @@
+//! Request handles for non-blocking data exchanges.
+//!
+//! A non-blocking exchange returns an [ExchangeRequest]. The request borrows the send and
+//! receive buffers of the exchange, so that they cannot be touched before the exchange
+//! has completed.
+//!
+//! As for the non-blocking operations of `rsmpi`, each request is registered with a
+//! [Scope]. Buffers of requests in a [LocalScope](mpi::request::LocalScope) created with
+//! [mpi::request::scope] must outlive the scope. If a request is leaked, e.g. with
+//! [std::mem::forget], the scope aborts the program at its end instead of releasing the
+//! buffers while MPI may still access them.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+use mpi::request::{Scope, StaticScope};
+
+/// Handle of a non-blocking exchange.
+///
+/// The send and receive buffers stay borrowed until the request is completed via
+/// [ExchangeRequest::wait] or [ExchangeRequest::test]. Dropping an incomplete request
+/// blocks until the exchange has completed.
+#[must_use = "dropping an exchange request blocks until the exchange has completed"]
+pub struct ExchangeRequest<'a, T, S: Scope<'a> = StaticScope> {
+    requests: Vec<mpi_sys::MPI_Request>,
+    // Count and displacement arrays that MPI may read until the exchange has completed.
+    _counts: Vec<Vec<i32>>,
+    scope: S,
+    _buffers: PhantomData<(&'a [T], &'a mut [T], Cell<&'a ()>)>,
+}
+
+impl<'a, T, S: Scope<'a>> ExchangeRequest<'a, T, S> {
+    /// Wrap raw MPI requests that together form one exchange and register them with `scope`.
+    ///
+    /// # Safety
+    /// The `requests` must be active non-persistent requests whose buffers live for `'a`
+    /// and whose count arrays are contained in `counts`.
+    pub(crate) unsafe fn from_raw(
+        requests: Vec<mpi_sys::MPI_Request>,
+        counts: Vec<Vec<i32>>,
+        scope: S,
+    ) -> Self {
+        scope.register();
+        Self {
+            requests,
+            _counts: counts,
+            scope,
+            _buffers: PhantomData,
+        }
+    }
+
+    /// Block until the exchange has completed.
+    pub fn wait(self) {
+        // Dropping the request waits for the exchange.
+        drop(self);
+    }
+
+    /// Test if the exchange has completed.
+    ///
+    /// Returns `Ok(())` if the exchange has completed. Otherwise, the request is handed back
+    /// in the `Err` variant.
+    pub fn test(mut self) -> Result<(), Self> {
+        let mut flag = 0;
+        unsafe {
+            mpi_sys::MPI_Testall(
+                self.requests.len() as i32,
+                self.requests.as_mut_ptr(),
+                &mut flag,
+                mpi_sys::RSMPI_STATUSES_IGNORE,
+            );
+        }
+        if flag != 0 {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<'a, T, S: Scope<'a>> Drop for ExchangeRequest<'a, T, S> {
+    fn drop(&mut self) {
+        // Waiting on completed requests is a no-op as MPI resets them to the null request.
+        unsafe {
+            mpi_sys::MPI_Waitall(
+                self.requests.len() as i32,
+                self.requests.as_mut_ptr(),
+                mpi_sys::RSMPI_STATUSES_IGNORE,
+            );
+            self.scope.unregister();
+        }
+    }
+}